@@ -0,0 +1,227 @@
+use crate::paths;
+use crate::runtime::RuntimeKind;
+use crate::transport::{SshClient, TransportKind};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// Run on the host by `nvim` before attach commands existed. Still used when it
+/// is installed there and neither `remote_script` nor an attach command is configured.
+pub const LEGACY_REMOTE_SCRIPT: &str = "/usr/local/bin/docker_tmux.sh";
+
+/// Run inside the container by `nvim` when no attach command or remote script is configured.
+const DEFAULT_ATTACH_COMMAND: [&str; 6] = ["tmux", "new", "-A", "-s", "devbox", "nvim"];
+
+/// Settings that can be given globally at the top of `config.toml` or per host
+/// in a `[hosts.<sshname>]` table. Per-host values win over global ones.
+/// Unknown keys are rejected so a misspelt setting isn't silently ignored.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    pub transport: Option<TransportKind>,
+    pub remote_script: Option<String>,
+    pub attach_command: Option<Vec<String>>,
+    pub runtime: Option<RuntimeKind>,
+    pub default_container: Option<String>,
+    pub ssh_args: Option<Vec<String>>,
+    pub jump_host: Option<String>,
+    pub multiplex: Option<bool>,
+    pub ssh_client: Option<SshClient>,
+    #[serde(default)]
+    pub containers: HashMap<String, ContainerOverrides>,
+}
+
+/// Per-container settings from a `[hosts.<sshname>.containers.<name>]` table.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerOverrides {
+    pub attach_command: Option<Vec<String>>,
+}
+
+/// The whole of `config.toml`: global settings plus a `[hosts]` table.
+#[derive(Deserialize, Debug, Default)]
+#[serde(try_from = "toml::Table")]
+pub struct Config {
+    pub defaults: HostConfig,
+    pub hosts: HashMap<String, HostConfig>,
+}
+
+// Split by hand: `#[serde(flatten)]` would quietly drop unknown global keys.
+impl TryFrom<toml::Table> for Config {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::Table) -> Result<Self, Self::Error> {
+        let hosts = match table.remove("hosts") {
+            Some(hosts) => hosts.try_into()?,
+            None => HashMap::new(),
+        };
+        Ok(Config {
+            defaults: table.try_into()?,
+            hosts,
+        })
+    }
+}
+
+/// Effective settings for one SSH host after merging config layers and built-in defaults.
+#[derive(Debug, Clone)]
+pub struct HostSettings {
+    pub sshname: String,
+    pub transport: TransportKind,
+    pub remote_script: Option<String>,
+    pub attach_command: Option<Vec<String>>,
+    pub containers: HashMap<String, ContainerOverrides>,
+    pub runtime: Option<RuntimeKind>,
+    pub default_container: Option<String>,
+    pub ssh_args: Vec<String>,
+    pub jump_host: Option<String>,
+    pub multiplex: bool,
+    pub ssh_client: SshClient,
+}
+
+impl Config {
+    /// Reads `config.toml`, treating a missing file as an empty configuration.
+    pub fn load() -> io::Result<Config> {
+        let path = paths::config_file();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        toml::from_str(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    pub fn host(&self, sshname: &str) -> HostSettings {
+        let host = self.hosts.get(sshname).cloned().unwrap_or_default();
+        let defaults = &self.defaults;
+        HostSettings {
+            sshname: sshname.to_string(),
+            transport: host.transport.or(defaults.transport).unwrap_or_default(),
+            remote_script: host
+                .remote_script
+                .or_else(|| defaults.remote_script.clone()),
+            attach_command: host
+                .attach_command
+                .or_else(|| defaults.attach_command.clone()),
+            containers: defaults
+                .containers
+                .clone()
+                .into_iter()
+                .chain(host.containers)
+                .collect(),
+            runtime: host.runtime.or(defaults.runtime),
+            default_container: host
+                .default_container
+                .or_else(|| defaults.default_container.clone()),
+            ssh_args: host
+                .ssh_args
+                .or_else(|| defaults.ssh_args.clone())
+                .unwrap_or_default(),
+            jump_host: host.jump_host.or_else(|| defaults.jump_host.clone()),
+            multiplex: host.multiplex.or(defaults.multiplex).unwrap_or(true),
+            ssh_client: host.ssh_client.or(defaults.ssh_client).unwrap_or_default(),
+        }
+    }
+}
+
+impl HostSettings {
+    /// Command run inside `container` to attach to it, with `{container}`
+    /// replaced by the container name.
+    pub fn attach_command(&self, container: &str) -> Vec<String> {
+        match self.configured_attach_command(container) {
+            Some(command) => command
+                .iter()
+                .map(|arg| arg.replace("{container}", container))
+                .collect(),
+            None => DEFAULT_ATTACH_COMMAND.map(str::to_string).to_vec(),
+        }
+    }
+
+    /// The attach command from config.toml, if any applies to `container`.
+    pub fn configured_attach_command(&self, container: &str) -> Option<&Vec<String>> {
+        self.containers
+            .get(container)
+            .and_then(|overrides| overrides.attach_command.as_ref())
+            .or(self.attach_command.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+transport = "mosh"
+attach_command = ["bash"]
+ssh_args = ["-A"]
+jump_host = "bastion"
+multiplex = false
+
+[containers.db]
+attach_command = ["psql"]
+
+[containers.api]
+attach_command = ["sh"]
+
+[hosts.prod]
+transport = "ssh"
+ssh_args = []
+multiplex = true
+
+[hosts.prod.containers.api]
+attach_command = ["node", "{container}"]
+"#;
+
+    fn config(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn host_values_win_over_global_ones() {
+        let prod = config(CONFIG).host("prod");
+        assert_eq!(prod.transport, TransportKind::Ssh);
+        assert!(prod.ssh_args.is_empty());
+        assert!(prod.multiplex);
+        assert_eq!(prod.jump_host.as_deref(), Some("bastion"));
+
+        let other = config(CONFIG).host("other");
+        assert_eq!(other.sshname, "other");
+        assert_eq!(other.transport, TransportKind::Mosh);
+        assert_eq!(other.ssh_args, ["-A"]);
+        assert!(!other.multiplex);
+    }
+
+    #[test]
+    fn container_overrides_merge_per_container() {
+        let prod = config(CONFIG).host("prod");
+        assert_eq!(prod.attach_command("api"), ["node", "api"]);
+        assert_eq!(prod.attach_command("db"), ["psql"]);
+        assert_eq!(prod.attach_command("web"), ["bash"]);
+
+        let other = config(CONFIG).host("other");
+        assert_eq!(other.attach_command("api"), ["sh"]);
+    }
+
+    #[test]
+    fn empty_config_uses_built_in_defaults() {
+        let host = config("").host("box");
+        assert_eq!(host.transport, TransportKind::default());
+        assert!(host.multiplex);
+        assert_eq!(host.configured_attach_command("api"), None);
+        assert_eq!(host.attach_command("api"), DEFAULT_ATTACH_COMMAND);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let typo = "[hosts.prod]\njumphost = \"bastion\"\n";
+        assert!(toml::from_str::<Config>(typo).is_err());
+        assert!(toml::from_str::<Config>("multiplx = false\n").is_err());
+        let container = "[hosts.prod.containers.api]\nattach = [\"sh\"]\n";
+        assert!(toml::from_str::<Config>(container).is_err());
+    }
+}
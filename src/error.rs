@@ -0,0 +1,88 @@
+use crate::remote::RemoteOutput;
+use std::fmt;
+
+/// Shown under `devbox --help`; keep in sync with [`DevboxError::exit_code`].
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  a command devbox ran failed, or any other error
+  2  invalid arguments
+  3  config.toml could not be read
+  4  ssh could not reach the host
+  5  no supported container runtime on the host
+  6  container not found
+  7  container has no network address
+  8  storage file is corrupt or from a newer devbox
+exec and shell exit with the status of the command they ran.";
+
+/// Everything a devbox command can fail with, mapped to distinct exit codes so
+/// scripts can react to the kind of failure.
+#[derive(Debug)]
+pub enum DevboxError {
+    /// A command devbox ran exited unsuccessfully, or a failure without a code of its own.
+    Failed(String),
+    /// Missing or contradictory arguments.
+    Usage(String),
+    Config(String),
+    /// ssh itself failed, as opposed to the command it ran.
+    SshFailed(String),
+    /// No supported runtime on the named host.
+    RuntimeMissing(String),
+    ContainerNotFound(String),
+    NoNetwork(String),
+    StorageCorrupt(String),
+}
+
+pub type Result<T> = std::result::Result<T, DevboxError>;
+
+impl DevboxError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DevboxError::Failed(_) => 1,
+            DevboxError::Usage(_) => 2,
+            DevboxError::Config(_) => 3,
+            DevboxError::SshFailed(_) => 4,
+            DevboxError::RuntimeMissing(_) => 5,
+            DevboxError::ContainerNotFound(_) => 6,
+            DevboxError::NoNetwork(_) => 7,
+            DevboxError::StorageCorrupt(_) => 8,
+        }
+    }
+
+    /// Classifies a failed remote command. ssh exits with 255 when it could not
+    /// reach the host, which no command of ours does.
+    pub fn remote(output: &RemoteOutput, context: &str) -> DevboxError {
+        let message = format!("{}: {}", context, output.stderr.trim());
+        if output.code == Some(255) {
+            DevboxError::SshFailed(message)
+        } else {
+            DevboxError::Failed(message)
+        }
+    }
+}
+
+impl fmt::Display for DevboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DevboxError::Failed(message) | DevboxError::Usage(message) => {
+                write!(f, "{}", message)
+            }
+            DevboxError::Config(message) => write!(f, "Invalid config: {}", message),
+            DevboxError::SshFailed(message) => write!(f, "SSH failed: {}", message),
+            DevboxError::RuntimeMissing(host) => write!(
+                f,
+                "No supported container runtime (docker, podman, nerdctl) found on '{}'",
+                host
+            ),
+            DevboxError::ContainerNotFound(container) => {
+                write!(f, "Container '{}' not found", container)
+            }
+            DevboxError::NoNetwork(message) => write!(f, "{}", message),
+            DevboxError::StorageCorrupt(message) => {
+                write!(f, "Storage file is unusable: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DevboxError {}
@@ -0,0 +1,124 @@
+use crate::glob;
+use crate::storage::ContainerInfo;
+
+/// A `list --filter` condition over stored container metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// `status=VALUE`, e.g. `running`, `exited`, or `gone` with `--live`.
+    Status(String),
+    /// `label=KEY` for any value, or `label=KEY=VALUE`.
+    Label(String, Option<String>),
+    /// `image=PATTERN`, a glob like `node:*`.
+    Image(String),
+}
+
+/// Parses a `--filter` argument.
+pub fn parse_filter(spec: &str) -> Result<Filter, String> {
+    let (key, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not KEY=VALUE", spec))?;
+    match key {
+        "status" => Ok(Filter::Status(value.to_string())),
+        "label" => Ok(match value.split_once('=') {
+            Some((label, value)) => Filter::Label(label.to_string(), Some(value.to_string())),
+            None => Filter::Label(value.to_string(), None),
+        }),
+        "image" => Ok(Filter::Image(value.to_string())),
+        _ => Err(format!(
+            "Unknown filter '{}'; expected status, label or image",
+            key
+        )),
+    }
+}
+
+impl Filter {
+    /// Whether `info` passes, where `status` is the state shown for it.
+    pub fn matches(&self, info: &ContainerInfo, status: &str) -> bool {
+        match self {
+            Filter::Status(wanted) => status == wanted,
+            Filter::Label(label, None) => info.labels.contains_key(label),
+            Filter::Label(label, Some(wanted)) => info.labels.get(label) == Some(wanted),
+            Filter::Image(pattern) => glob::matches(pattern, &info.image),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn container() -> ContainerInfo {
+        serde_json::from_value(json!({
+            "name": "api",
+            "image": "node:20-alpine",
+            "status": "running",
+            "labels": { "team": "web", "tier": "" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_each_filter_kind() {
+        assert_eq!(
+            parse_filter("status=exited"),
+            Ok(Filter::Status("exited".to_string()))
+        );
+        assert_eq!(
+            parse_filter("label=team"),
+            Ok(Filter::Label("team".to_string(), None))
+        );
+        assert_eq!(
+            parse_filter("label=team=web=blue"),
+            Ok(Filter::Label(
+                "team".to_string(),
+                Some("web=blue".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_filter("image=node:*"),
+            Ok(Filter::Image("node:*".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        assert!(parse_filter("status").is_err());
+        assert!(parse_filter("name=api").is_err());
+    }
+
+    #[test]
+    fn matches_container_metadata() {
+        let info = container();
+        let matching = [
+            "status=running",
+            "label=team",
+            "label=tier",
+            "label=team=web",
+            "label=tier=",
+            "image=node:*",
+            "image=node:??-alpine",
+        ];
+        for spec in matching {
+            assert!(
+                parse_filter(spec).unwrap().matches(&info, "running"),
+                "{}",
+                spec
+            );
+        }
+        let other = [
+            "status=exited",
+            "label=owner",
+            "label=team=api",
+            "image=python*",
+        ];
+        for spec in other {
+            assert!(
+                !parse_filter(spec).unwrap().matches(&info, "running"),
+                "{}",
+                spec
+            );
+        }
+        assert!(parse_filter("status=gone").unwrap().matches(&info, "gone"));
+    }
+}
@@ -0,0 +1,673 @@
+use crate::remote::RemoteExecutor;
+use crate::storage::{DevboxStorage, ForwardRecord};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, TcpListener};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command as ShellCommand, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a detached tunnel must survive before it counts as established.
+const STARTUP_GRACE: Duration = Duration::from_secs(2);
+
+/// How often a supervised tunnel's local ports are checked.
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// Local probes can't see the container, so its address is looked up again every this many probes.
+const RESOLVE_EVERY: u32 = 6;
+/// Reconnect delays double from the first to the last.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A tunnel that stayed up this long starts the next reconnect from the initial delay again.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Prefix marking a Unix socket path in a port spec.
+const UNIX_PREFIX: &str = "unix:";
+
+/// One side of a forward: a TCP port, or a Unix socket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Endpoint {
+    Port(u16),
+    Unix(String),
+}
+
+impl Endpoint {
+    /// The endpoint as written in an `ssh -L` spec, where sockets are bare paths.
+    fn ssh_spec(&self) -> String {
+        match self {
+            Endpoint::Port(port) => port.to_string(),
+            Endpoint::Unix(path) => path.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Port(port) => write!(f, "{}", port),
+            Endpoint::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path),
+        }
+    }
+}
+
+/// One forward: `local` on this machine to `container` in the container.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    pub local: Endpoint,
+    pub container: Endpoint,
+}
+
+/// Where ssh connects each mapping to on the SSH host: the container's address,
+/// with container-side sockets already translated to paths on the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tunnel {
+    pub target_host: String,
+    pub mappings: Vec<PortMapping>,
+}
+
+impl Tunnel {
+    /// The `-L` argument for each mapping.
+    pub fn ssh_forwards(&self) -> Vec<String> {
+        self.mappings
+            .iter()
+            .map(|mapping| match &mapping.container {
+                Endpoint::Port(port) => {
+                    format!("{}:{}:{}", mapping.local.ssh_spec(), self.target_host, port)
+                }
+                Endpoint::Unix(path) => format!("{}:{}", mapping.local.ssh_spec(), path),
+            })
+            .collect()
+    }
+
+    /// Whether any mapping listens on a local socket.
+    pub fn has_local_sockets(&self) -> bool {
+        self.mappings
+            .iter()
+            .any(|mapping| matches!(mapping.local, Endpoint::Unix(_)))
+    }
+}
+
+/// Parses one `fp` port argument: `PORT`, `LOCAL:CONTAINER`, `START-END`, or
+/// `START-END:START-END` with ranges of equal length. Either side of a
+/// `LOCAL:CONTAINER` pair may instead be a socket, as `unix:/path`.
+pub fn parse_port_spec(spec: &str) -> Result<Vec<PortMapping>, String> {
+    let (local, container) = split_spec(spec)?;
+    if local.starts_with(UNIX_PREFIX) || container.starts_with(UNIX_PREFIX) {
+        return Ok(vec![PortMapping {
+            local: parse_endpoint(local)?,
+            container: parse_endpoint(container)?,
+        }]);
+    }
+
+    let local = parse_port_range(local)?;
+    let container = parse_port_range(container)?;
+
+    if local.len() != container.len() {
+        return Err(format!(
+            "'{}' maps {} local ports to {} container ports",
+            spec,
+            local.len(),
+            container.len()
+        ));
+    }
+    Ok(local
+        .zip(container)
+        .map(|(local, container)| PortMapping {
+            local: Endpoint::Port(local),
+            container: Endpoint::Port(container),
+        })
+        .collect())
+}
+
+/// Splits a spec into its local and container sides. Socket paths may contain
+/// colons themselves, so the split happens before a `unix:` or a trailing port.
+fn split_spec(spec: &str) -> Result<(&str, &str), String> {
+    if let Some(i) = spec.find(":unix:") {
+        return Ok((&spec[..i], &spec[i + 1..]));
+    }
+    if spec.starts_with(UNIX_PREFIX) {
+        return match spec.rsplit_once(':') {
+            Some((local, port)) if local != "unix" && parse_port(port).is_ok() => Ok((local, port)),
+            _ => Err(format!(
+                "'{}' needs both sides, like unix:/tmp/app.sock:8080",
+                spec
+            )),
+        };
+    }
+    Ok(spec.split_once(':').unwrap_or((spec, spec)))
+}
+
+fn parse_endpoint(side: &str) -> Result<Endpoint, String> {
+    match side.strip_prefix(UNIX_PREFIX) {
+        Some(path) if path.starts_with('/') => Ok(Endpoint::Unix(path.to_string())),
+        Some(_) => Err(format!("Socket path in '{}' must be absolute", side)),
+        None => parse_port(side).map(Endpoint::Port),
+    }
+}
+
+fn parse_port_range(range: &str) -> Result<std::ops::RangeInclusive<u16>, String> {
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let (start, end) = (parse_port(start)?, parse_port(end)?);
+    if start > end {
+        return Err(format!("Port range '{}' is reversed", range));
+    }
+    Ok(start..=end)
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!("'{}' is not a valid port number (1-65535)", port)),
+    }
+}
+
+/// Renders mappings compactly, folding consecutive runs of ports back into ranges.
+pub struct Mappings<'a>(pub &'a [PortMapping]);
+
+impl fmt::Display for Mappings<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut runs: Vec<(&PortMapping, &PortMapping)> = Vec::new();
+        for mapping in self.0 {
+            match runs.last_mut() {
+                Some((_, last)) if continues(last, mapping) => *last = mapping,
+                _ => runs.push((mapping, mapping)),
+            }
+        }
+
+        for (i, (first, last)) in runs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            if first == last {
+                write!(f, "{}:{}", first.local, first.container)?;
+            } else {
+                write!(
+                    f,
+                    "{}-{}:{}-{}",
+                    first.local, last.local, first.container, last.container
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `next` extends a run of ports ending at `last` on both sides.
+fn continues(last: &PortMapping, next: &PortMapping) -> bool {
+    match (&last.local, &last.container, &next.local, &next.container) {
+        (
+            Endpoint::Port(last_local),
+            Endpoint::Port(last_container),
+            Endpoint::Port(local),
+            Endpoint::Port(container),
+        ) => {
+            last_local.checked_add(1) == Some(*local)
+                && last_container.checked_add(1) == Some(*container)
+        }
+        _ => false,
+    }
+}
+
+/// Starts `ssh -L` in the background, detached from the terminal, and returns its pid.
+pub fn spawn_detached(executor: &RemoteExecutor, tunnel: &Tunnel) -> io::Result<u32> {
+    if executor.uses_native() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--detach needs the OpenSSH client",
+        ));
+    }
+
+    // Nothing can answer a prompt once detached, and a tunnel that fails to
+    // bind should exit instead of lingering without forwards.
+    let mut command = executor.forward_command(
+        tunnel,
+        &["-o", "BatchMode=yes", "-o", "ExitOnForwardFailure=yes"],
+    );
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        // Own process group, so closing the terminal doesn't hang it up.
+        .process_group(0);
+    let mut child = command.spawn()?;
+
+    thread::sleep(STARTUP_GRACE);
+    if let Some(status) = child.try_wait()? {
+        let output = child.wait_with_output()?;
+        return Err(io::Error::other(format!(
+            "ssh exited with {}: {}",
+            status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // Stop capturing stderr so a chatty ssh can't block on a full pipe.
+    drop(child.stderr.take());
+    Ok(child.id())
+}
+
+/// Keeps `tunnel` running in the foreground until interrupted, restarting ssh
+/// with backoff whenever it exits or a local endpoint stops accepting
+/// connections. `resolve` is asked for a fresh tunnel periodically and before
+/// each restart, so a recreated container is followed to its new address.
+pub fn supervise<E: fmt::Display>(
+    executor: &RemoteExecutor,
+    mut tunnel: Tunnel,
+    resolve: impl Fn() -> Result<Tunnel, E>,
+) -> io::Result<()> {
+    if executor.uses_native() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--supervise needs the OpenSSH client",
+        ));
+    }
+
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let reason = match run_supervised(executor, &tunnel, &resolve)? {
+            Stopped::Moved(fresh) => {
+                log_move(&tunnel, &fresh);
+                tunnel = fresh;
+                backoff = INITIAL_BACKOFF;
+                continue;
+            }
+            Stopped::Failed(reason) => reason,
+        };
+        if started.elapsed() >= STABLE_AFTER {
+            backoff = INITIAL_BACKOFF;
+        }
+        log(&format!(
+            "Tunnel down ({}), reconnecting in {}s",
+            reason,
+            backoff.as_secs()
+        ));
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+
+        match resolve() {
+            Ok(fresh) if fresh != tunnel => {
+                log_move(&tunnel, &fresh);
+                tunnel = fresh;
+            }
+            Ok(_) => {}
+            Err(e) => log(&format!(
+                "Could not re-resolve the container ({}), retrying {}",
+                e, tunnel.target_host
+            )),
+        }
+    }
+}
+
+/// Why a supervised ssh was stopped.
+enum Stopped {
+    /// ssh exited or a local endpoint failed its probe.
+    Failed(String),
+    /// The container now resolves to a different tunnel.
+    Moved(Tunnel),
+}
+
+/// Runs one ssh until it exits, fails a probe or the container moves.
+fn run_supervised<E>(
+    executor: &RemoteExecutor,
+    tunnel: &Tunnel,
+    resolve: &impl Fn() -> Result<Tunnel, E>,
+) -> io::Result<Stopped> {
+    // Keepalives notice a link that died while the laptop slept; a tunnel that
+    // can't bind should exit rather than linger without forwards.
+    let mut child = executor
+        .forward_command(
+            tunnel,
+            &[
+                "-o",
+                "ExitOnForwardFailure=yes",
+                "-o",
+                "ServerAliveInterval=15",
+                "-o",
+                "ServerAliveCountMax=3",
+            ],
+        )
+        .stdin(Stdio::null())
+        .spawn()?;
+
+    let mut connected = false;
+    let mut probes: u32 = 0;
+    loop {
+        thread::sleep(PROBE_INTERVAL);
+        probes = probes.wrapping_add(1);
+        if let Some(status) = child.try_wait()? {
+            return Ok(Stopped::Failed(format!("ssh exited with {}", status)));
+        }
+        match tunnel
+            .mappings
+            .iter()
+            .find(|mapping| !probe(&mapping.local))
+        {
+            Some(mapping) => {
+                kill(&mut child);
+                return Ok(Stopped::Failed(format!(
+                    "local {} no longer listening",
+                    mapping.local
+                )));
+            }
+            None if !connected => {
+                log(&format!(
+                    "Tunnel up to {}: {}",
+                    tunnel.target_host,
+                    Mappings(&tunnel.mappings)
+                ));
+                connected = true;
+            }
+            None => {}
+        }
+        // A failed lookup while the tunnel is healthy is retried at the next interval.
+        if probes.is_multiple_of(RESOLVE_EVERY) {
+            if let Ok(fresh) = resolve() {
+                if fresh != *tunnel {
+                    kill(&mut child);
+                    return Ok(Stopped::Moved(fresh));
+                }
+            }
+        }
+    }
+}
+
+fn log_move(from: &Tunnel, to: &Tunnel) {
+    log(&format!(
+        "Container moved from {} to {}",
+        from.target_host, to.target_host
+    ));
+}
+
+/// Whether ssh still listens on `endpoint`. Connecting would open a channel
+/// to the service on every probe, so this only checks the listener is there;
+/// a dead link is left to ssh's keepalives.
+fn probe(endpoint: &Endpoint) -> bool {
+    match endpoint {
+        Endpoint::Port(port) => TcpListener::bind((Ipv4Addr::LOCALHOST, *port))
+            .is_err_and(|e| e.kind() == io::ErrorKind::AddrInUse),
+        Endpoint::Unix(path) => {
+            fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+        }
+    }
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Reconnect events, prefixed with the UTC time of day.
+fn log(message: &str) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+        % 86400;
+    eprintln!(
+        "[{:02}:{:02}:{:02}] {}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        message
+    );
+}
+
+/// Records a tunnel started by [`spawn_detached`] and returns its id.
+pub fn record(
+    storage: &mut DevboxStorage,
+    pid: u32,
+    sshname: &str,
+    container: &str,
+    mappings: &[PortMapping],
+) -> u32 {
+    let id = storage.forwards.iter().map(|f| f.id).max().unwrap_or(0) + 1;
+    storage.forwards.push(ForwardRecord {
+        id,
+        pid,
+        sshname: sshname.to_string(),
+        container: container.to_string(),
+        ports: mappings.to_vec(),
+        started: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        process_started: process_started(pid).unwrap_or_default(),
+    });
+    id
+}
+
+/// Drops records whose ssh process is gone, returning how many were removed.
+pub fn prune(storage: &mut DevboxStorage) -> usize {
+    let before = storage.forwards.len();
+    storage.forwards.retain(is_running);
+    before - storage.forwards.len()
+}
+
+/// Terminates the forward's ssh process. A process that already exited, or a
+/// different process that has since been given its pid, counts as stopped.
+pub fn stop(forward: &ForwardRecord) -> io::Result<()> {
+    if !is_running(forward) {
+        return Ok(());
+    }
+    let status = ShellCommand::new("kill")
+        .arg(forward.pid.to_string())
+        .stderr(Stdio::null())
+        .status()?;
+    if status.success() || !is_running(forward) {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "Failed to kill pid {}",
+            forward.pid
+        )))
+    }
+}
+
+/// Whether the forward's ssh still runs under its recorded pid. Records
+/// without a start time are recognized by their ssh command line instead.
+fn is_running(forward: &ForwardRecord) -> bool {
+    match process_started(forward.pid) {
+        None => false,
+        Some(started) if !forward.process_started.is_empty() => started == forward.process_started,
+        Some(_) => ps(forward.pid, "args").is_some_and(|args| is_tunnel_command(&args, forward)),
+    }
+}
+
+/// Start time of `pid`, or `None` when no such process exists.
+fn process_started(pid: u32) -> Option<String> {
+    ps(pid, "lstart")
+}
+
+/// One `ps` column for `pid`.
+fn ps(pid: u32, column: &str) -> Option<String> {
+    let output = ShellCommand::new("ps")
+        .arg("-o")
+        .arg(format!("{}=", column))
+        .arg("-p")
+        .arg(pid.to_string())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Whether `args` is an ssh command forwarding every local endpoint of `forward`.
+fn is_tunnel_command(args: &str, forward: &ForwardRecord) -> bool {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let is_ssh = words
+        .first()
+        .is_some_and(|program| program.rsplit('/').next() == Some("ssh"));
+    is_ssh
+        && forward.ports.iter().all(|mapping| {
+            let prefix = format!("{}:", mapping.local.ssh_spec());
+            words
+                .windows(2)
+                .any(|pair| pair[0] == "-L" && pair[1].starts_with(&prefix))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports(pairs: &[(u16, u16)]) -> Vec<PortMapping> {
+        pairs
+            .iter()
+            .map(|&(local, container)| PortMapping {
+                local: Endpoint::Port(local),
+                container: Endpoint::Port(container),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_ports() {
+        assert_eq!(parse_port_spec("8080"), Ok(ports(&[(8080, 8080)])));
+        assert_eq!(parse_port_spec("8080:80"), Ok(ports(&[(8080, 80)])));
+    }
+
+    #[test]
+    fn port_ranges() {
+        assert_eq!(
+            parse_port_spec("8080-8082:9000-9002"),
+            Ok(ports(&[(8080, 9000), (8081, 9001), (8082, 9002)]))
+        );
+        assert_eq!(
+            parse_port_spec("3000-3001"),
+            Ok(ports(&[(3000, 3000), (3001, 3001)]))
+        );
+        assert_eq!(
+            parse_port_spec("5000-5000:6000"),
+            Ok(ports(&[(5000, 6000)]))
+        );
+    }
+
+    #[test]
+    fn reversed_range_is_rejected() {
+        let error = parse_port_spec("8090-8080:8090-8080").unwrap_err();
+        assert!(error.contains("reversed"), "{}", error);
+    }
+
+    #[test]
+    fn range_length_mismatch_is_rejected() {
+        let error = parse_port_spec("8080-8082:9000-9001").unwrap_err();
+        assert!(error.contains("maps 3 local ports to 2"), "{}", error);
+        assert!(parse_port_spec("8080-8081:80").is_err());
+    }
+
+    #[test]
+    fn invalid_ports_are_rejected() {
+        for spec in ["0", "65536", "http", "8080:", ":80", "80-", "-80", ""] {
+            assert!(parse_port_spec(spec).is_err(), "{} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn split_spec_finds_the_socket_side() {
+        assert_eq!(
+            split_spec("8080:unix:/run/app.sock"),
+            Ok(("8080", "unix:/run/app.sock"))
+        );
+        assert_eq!(
+            split_spec("unix:/tmp/app.sock:8080"),
+            Ok(("unix:/tmp/app.sock", "8080"))
+        );
+        assert_eq!(
+            split_spec("unix:/tmp/a.sock:unix:/run/b.sock"),
+            Ok(("unix:/tmp/a.sock", "unix:/run/b.sock"))
+        );
+        assert_eq!(split_spec("8080:80"), Ok(("8080", "80")));
+    }
+
+    #[test]
+    fn split_spec_allows_colons_in_paths() {
+        assert_eq!(
+            split_spec("unix:/tmp/a:b.sock:unix:/run/c:d.sock"),
+            Ok(("unix:/tmp/a:b.sock", "unix:/run/c:d.sock"))
+        );
+        assert_eq!(
+            split_spec("unix:/tmp/a:b.sock:5432"),
+            Ok(("unix:/tmp/a:b.sock", "5432"))
+        );
+        assert_eq!(
+            split_spec("5432:unix:/run/x:y.sock"),
+            Ok(("5432", "unix:/run/x:y.sock"))
+        );
+    }
+
+    #[test]
+    fn socket_specs() {
+        assert_eq!(
+            parse_port_spec("unix:/tmp/pg.sock:unix:/run/pg.sock"),
+            Ok(vec![PortMapping {
+                local: Endpoint::Unix("/tmp/pg.sock".to_string()),
+                container: Endpoint::Unix("/run/pg.sock".to_string()),
+            }])
+        );
+        assert!(parse_port_spec("unix:/tmp/app.sock").is_err());
+        assert!(parse_port_spec("unix:relative.sock:8080").is_err());
+        assert!(parse_port_spec("8080-8081:unix:/run/app.sock").is_err());
+    }
+
+    fn record(ports: Vec<PortMapping>) -> ForwardRecord {
+        ForwardRecord {
+            id: 1,
+            pid: 4242,
+            sshname: "dev".to_string(),
+            container: "api".to_string(),
+            ports,
+            started: 0,
+            process_started: String::new(),
+        }
+    }
+
+    #[test]
+    fn tunnel_command_must_be_ssh_forwarding_every_endpoint() {
+        let forward = record(vec![
+            PortMapping {
+                local: Endpoint::Port(8080),
+                container: Endpoint::Port(80),
+            },
+            PortMapping {
+                local: Endpoint::Unix("/tmp/pg.sock".to_string()),
+                container: Endpoint::Unix("/run/pg.sock".to_string()),
+            },
+        ]);
+        let args = "/usr/bin/ssh -o ControlPath=none -N -L 8080:172.17.0.2:80 \
+                    -L /tmp/pg.sock:/proc/1/root/run/pg.sock -- dev";
+        assert!(is_tunnel_command(args, &forward));
+        assert!(!is_tunnel_command(
+            "ssh -N -L 8080:172.17.0.2:80 -- dev",
+            &forward
+        ));
+        assert!(!is_tunnel_command("sleep 300", &forward));
+        assert!(!is_tunnel_command(
+            "vim -L 8080:x -L /tmp/pg.sock:y",
+            &forward
+        ));
+    }
+
+    #[test]
+    fn probe_sees_listeners_without_connecting() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(probe(&Endpoint::Port(port)));
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+        drop(listener);
+        assert!(!probe(&Endpoint::Port(port)));
+
+        let path = std::env::temp_dir().join(format!("devbox-probe-{}.sock", std::process::id()));
+        let socket = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let endpoint = Endpoint::Unix(path.to_string_lossy().into_owned());
+        assert!(probe(&endpoint));
+        drop(socket);
+        fs::remove_file(&path).unwrap();
+        assert!(!probe(&endpoint));
+    }
+}
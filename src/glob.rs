@@ -0,0 +1,52 @@
+/// Shell-style wildcard matching: `*` matches any run of characters, `?` exactly one.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    matches_bytes(pattern.as_bytes(), text.as_bytes())
+}
+
+fn matches_bytes(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            matches_bytes(&pattern[1..], text)
+                || (!text.is_empty() && matches_bytes(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => matches_bytes(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => matches_bytes(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn literal_patterns() {
+        assert!(matches("api", "api"));
+        assert!(matches("", ""));
+        assert!(!matches("api", "api2"));
+        assert!(!matches("api", "API"));
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("web-*", "web-"));
+        assert!(matches("web-*", "web-frontend"));
+        assert!(matches("*-db", "orders-db"));
+        assert!(matches("node:*-alpine", "node:20-alpine"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("web-*", "api-web"));
+        assert!(!matches("*-db", "orders-db-1"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("api?", "api1"));
+        assert!(matches("??", "ab"));
+        assert!(!matches("api?", "api"));
+        assert!(!matches("api?", "api12"));
+        assert!(matches("postgres:1?.*", "postgres:16.2"));
+    }
+}
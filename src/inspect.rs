@@ -0,0 +1,369 @@
+use crate::error::{DevboxError, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// The subset of `docker inspect` output devbox relies on. Podman and
+/// nerdctl emit the same Docker-compatible shape.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerInspect {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub created: String,
+    #[serde(default)]
+    pub config: ContainerConfig,
+    #[serde(default)]
+    pub state: ContainerState,
+    #[serde(default)]
+    pub network_settings: NetworkSettings,
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+    #[serde(default)]
+    pub image: String,
+    pub labels: Option<BTreeMap<String, String>>,
+    pub exposed_ports: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerState {
+    #[serde(default)]
+    pub status: String,
+    /// Host pid of the container's init process; 0 when it isn't running.
+    #[serde(default)]
+    pub pid: i64,
+    #[serde(default)]
+    pub started_at: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct Mount {
+    /// Path on the host; empty for mounts with no host backing, such as tmpfs.
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub destination: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct NetworkSettings {
+    // Sorted so the "first" network matches the `keys[0]` lookup devbox used to do with jq.
+    pub networks: Option<BTreeMap<String, EndpointSettings>>,
+    /// Published ports, keyed like `5432/tcp`.
+    pub ports: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EndpointSettings {
+    #[serde(rename = "IPAddress", default)]
+    pub ip_address: String,
+    /// The host's address on this network, which the container can reach.
+    #[serde(rename = "Gateway", default)]
+    pub gateway: String,
+}
+
+impl ContainerInspect {
+    /// First network the container is attached to, with its endpoint settings.
+    pub fn first_network(&self) -> Option<(&str, &EndpointSettings)> {
+        self.network_settings
+            .networks
+            .as_ref()?
+            .iter()
+            .next()
+            .map(|(name, endpoint)| (name.as_str(), endpoint))
+    }
+
+    /// Where `path` inside the container is on the host through a mount covering it.
+    pub fn mounted_path(&self, path: &str) -> Option<String> {
+        let mount = self
+            .mounts
+            .iter()
+            .filter(|mount| !mount.source.is_empty())
+            .filter(|mount| within(path, mount.destination.trim_end_matches('/')))
+            .max_by_key(|mount| mount.destination.len())?;
+        let rest = &path[mount.destination.trim_end_matches('/').len()..];
+        Some(format!("{}{}", mount.source.trim_end_matches('/'), rest))
+    }
+
+    /// `path` through the container's root under `/proc`, which only root can
+    /// enter on the host. `None` when the container isn't running.
+    pub fn proc_path(&self, path: &str) -> Option<String> {
+        (self.state.pid > 0).then(|| format!("/proc/{}/root{}", self.state.pid, path))
+    }
+
+    /// Container-side TCP ports that are exposed or published, sorted and deduplicated.
+    pub fn tcp_ports(&self) -> Vec<u16> {
+        let exposed = self
+            .config
+            .exposed_ports
+            .iter()
+            .flat_map(|ports| ports.keys());
+        let published = self
+            .network_settings
+            .ports
+            .iter()
+            .flat_map(|ports| ports.keys());
+        let mut ports: Vec<u16> = exposed
+            .chain(published)
+            .filter_map(|key| key.strip_suffix("/tcp")?.parse().ok())
+            .collect();
+        ports.sort_unstable();
+        ports.dedup();
+        ports
+    }
+}
+
+/// Whether `path` is `dir` or inside it.
+fn within(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Seconds since the Unix epoch for an RFC 3339 timestamp as printed by
+/// `inspect`, e.g. `2024-05-01T12:00:00.123456789Z` or `...+02:00`. `None` for
+/// unparseable values and for Docker's `0001-01-01T00:00:00Z` "never".
+pub fn parse_timestamp(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let (clock, offset) = time.split_at(time.find(['Z', 'z', '+', '-']).unwrap_or(time.len()));
+    let mut clock = clock
+        .split('.')
+        .next()?
+        .splitn(3, ':')
+        .map(str::parse::<i64>);
+    let (hour, minute, second) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+    );
+    let offset = match offset.split_at_checked(1) {
+        None | Some(("Z" | "z", _)) => 0,
+        Some((sign, rest)) => {
+            let (hours, minutes) = rest.split_once(':')?;
+            let seconds = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            if sign == "-" {
+                -seconds
+            } else {
+                seconds
+            }
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(seconds).ok()
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Howard Hinnant's algorithm, with years starting in March so leap days come last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Parses the JSON array printed by `<runtime> inspect <container>...`.
+pub fn parse_all(json: &str) -> Result<Vec<ContainerInspect>> {
+    serde_json::from_str(json)
+        .map_err(|e| DevboxError::Failed(format!("Unexpected container inspect output: {}", e)))
+}
+
+/// Parses the inspect output for a single container.
+pub fn parse(json: &str) -> Result<ContainerInspect> {
+    parse_all(json)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| DevboxError::Failed("Container inspect returned no results".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ContainerInfo;
+
+    /// `docker inspect api` from Docker 27, trimmed of fields devbox ignores.
+    const DOCKER_INSPECT: &str = r#"[
+    {
+        "Id": "6f1c2b8e4a0d9c7b5e3f1a2d4c6b8e0f2a4c6e8b0d2f4a6c8e0b2d4f6a8c0e2b",
+        "Created": "2026-10-18T09:58:41.512345678Z",
+        "Path": "docker-entrypoint.sh",
+        "State": {
+            "Status": "running",
+            "Running": true,
+            "Pid": 4242,
+            "ExitCode": 0,
+            "StartedAt": "2026-10-18T10:00:00.123456789Z",
+            "FinishedAt": "0001-01-01T00:00:00Z"
+        },
+        "Name": "/api",
+        "RestartCount": 0,
+        "Mounts": [
+            {
+                "Type": "volume",
+                "Name": "pg",
+                "Source": "/var/lib/docker/volumes/pg/_data",
+                "Destination": "/var/run/postgresql",
+                "Driver": "local",
+                "Mode": "z",
+                "RW": true,
+                "Propagation": ""
+            }
+        ],
+        "Config": {
+            "Hostname": "6f1c2b8e4a0d",
+            "ExposedPorts": {
+                "3000/tcp": {},
+                "5353/udp": {},
+                "8080/tcp": {}
+            },
+            "Env": ["PATH=/usr/local/bin:/usr/bin:/bin"],
+            "Cmd": ["node", "server.js"],
+            "Image": "node:20-bookworm",
+            "Labels": {
+                "com.docker.compose.project": "shop",
+                "team": "web"
+            }
+        },
+        "NetworkSettings": {
+            "SandboxKey": "/var/run/docker/netns/2b1f0c9a8e7d",
+            "Ports": {
+                "3000/tcp": [{ "HostIp": "0.0.0.0", "HostPort": "3000" }],
+                "5353/udp": null,
+                "8080/tcp": null,
+                "9229/tcp": [{ "HostIp": "127.0.0.1", "HostPort": "9229" }]
+            },
+            "IPAddress": "",
+            "Networks": {
+                "shop_default": {
+                    "IPAMConfig": null,
+                    "Aliases": ["api"],
+                    "MacAddress": "02:42:ac:12:00:03",
+                    "NetworkID": "9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b2a1f0e9d8c",
+                    "Gateway": "172.18.0.1",
+                    "IPAddress": "172.18.0.3",
+                    "IPPrefixLen": 16,
+                    "DNSNames": ["api", "6f1c2b8e4a0d"]
+                },
+                "zz_monitoring": {
+                    "Gateway": "172.19.0.1",
+                    "IPAddress": "172.19.0.7"
+                }
+            }
+        }
+    }
+]"#;
+
+    #[test]
+    fn realistic_docker_inspect() {
+        let inspect = parse(DOCKER_INSPECT).unwrap();
+        let (network, endpoint) = inspect.first_network().unwrap();
+        assert_eq!(network, "shop_default");
+        assert_eq!(endpoint.ip_address, "172.18.0.3");
+        assert_eq!(endpoint.gateway, "172.18.0.1");
+        assert_eq!(inspect.tcp_ports(), [3000, 8080, 9229]);
+        assert_eq!(inspect.state.pid, 4242);
+
+        let info = ContainerInfo::from(inspect);
+        assert_eq!(info.name, "api");
+        assert_eq!(info.image, "node:20-bookworm");
+        assert_eq!(info.status, "running");
+        assert_eq!(info.started, "2026-10-18T10:00:00.123456789Z");
+        assert_eq!(info.ip(), Some("172.18.0.3"));
+        assert_eq!(info.networks["zz_monitoring"], "172.19.0.7");
+        assert_eq!(info.ports, ["3000/tcp", "5353/udp", "8080/tcp"]);
+        assert_eq!(info.labels["team"], "web");
+    }
+
+    fn container(pid: i64) -> ContainerInspect {
+        parse(&format!(
+            r#"[{{
+                "State": {{ "Pid": {} }},
+                "Mounts": [
+                    {{ "Source": "/var/lib/docker/volumes/pg/_data", "Destination": "/var/run/postgresql" }},
+                    {{ "Source": "/srv/app", "Destination": "/app/" }},
+                    {{ "Source": "/srv/app-sockets", "Destination": "/app/sockets" }},
+                    {{ "Source": "", "Destination": "/tmp" }}
+                ]
+            }}]"#,
+            pid
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn socket_under_a_mount_maps_to_its_source() {
+        let inspect = container(4242);
+        assert_eq!(
+            inspect
+                .mounted_path("/var/run/postgresql/.s.PGSQL.5432")
+                .as_deref(),
+            Some("/var/lib/docker/volumes/pg/_data/.s.PGSQL.5432")
+        );
+        assert_eq!(
+            inspect.mounted_path("/app/run.sock").as_deref(),
+            Some("/srv/app/run.sock")
+        );
+        // The most specific mount wins.
+        assert_eq!(
+            inspect.mounted_path("/app/sockets/api.sock").as_deref(),
+            Some("/srv/app-sockets/api.sock")
+        );
+    }
+
+    #[test]
+    fn socket_outside_mounts_goes_through_proc() {
+        let inspect = container(4242);
+        assert_eq!(inspect.mounted_path("/run/app.sock"), None);
+        // tmpfs has no host path, and a prefix is not a parent directory.
+        assert_eq!(inspect.mounted_path("/tmp/app.sock"), None);
+        assert_eq!(inspect.mounted_path("/application.sock"), None);
+        assert_eq!(
+            inspect.proc_path("/run/app.sock").as_deref(),
+            Some("/proc/4242/root/run/app.sock")
+        );
+        assert_eq!(container(0).proc_path("/run/app.sock"), None);
+    }
+
+    #[test]
+    fn timestamps_in_utc() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_timestamp("2026-10-18T10:00:00.123456789Z"),
+            Some(1_792_317_600)
+        );
+        assert_eq!(parse_timestamp("2024-02-29T12:30:15Z"), Some(1_709_209_815));
+    }
+
+    #[test]
+    fn timestamps_with_offsets() {
+        let utc = parse_timestamp("2026-10-18T10:00:00Z");
+        assert_eq!(parse_timestamp("2026-10-18T12:00:00+02:00"), utc);
+        assert_eq!(parse_timestamp("2026-10-18T04:30:00.5-05:30"), utc);
+        assert_eq!(parse_timestamp("2026-10-18T10:00:00"), utc);
+    }
+
+    #[test]
+    fn unset_and_malformed_timestamps() {
+        // Docker reports containers that never started with the zero time.
+        assert_eq!(parse_timestamp("0001-01-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("2026-10-18"), None);
+        assert_eq!(parse_timestamp("2026-10-18T10:00Z"), None);
+        assert_eq!(parse_timestamp("2026-10-18T10:00:00+0200"), None);
+    }
+}
@@ -1,22 +1,58 @@
-use clap::{Arg, Command};
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, BufWriter};
-use std::path::Path;
-use std::process::Command as ShellCommand;
-use serde::{Deserialize, Serialize};
+mod config;
+mod error;
+mod filter;
+mod forward;
+mod glob;
+mod inspect;
+#[cfg(feature = "native-ssh")]
+mod native;
+mod output;
+mod paths;
+mod remote;
+mod runtime;
+mod storage;
+mod transport;
 
-const STORAGE_FILE: &str = "~/.devbox_storage.json";
-
-#[derive(Serialize, Deserialize, Debug)]
-struct DevboxStorage {
-    containers: HashMap<String, Vec<String>>, // Maps SSH names to container lists
-}
+use clap::{builder::PossibleValuesParser, Arg, ArgAction, Command};
+use config::{Config, HostSettings, LEGACY_REMOTE_SCRIPT};
+use error::{DevboxError, Result};
+use filter::Filter;
+use forward::{Endpoint, Mappings, PortMapping, Tunnel};
+use inspect::ContainerInspect;
+use output::{OutputFormat, Table};
+use remote::{RemoteExecutor, RemoteOutput};
+use runtime::RuntimeKind;
+use serde::Serialize;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener};
+use std::path::PathBuf;
+use std::process::{self, ExitStatus};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use storage::{load_storage, update_storage, ContainerInfo};
 
 fn main() {
     let matches = Command::new("devbox")
         .version("1.0")
         .about("Development tool for managing container connections")
+        .after_help(error::EXIT_CODES_HELP)
+        .arg(
+            Arg::new("storage")
+                .long("storage")
+                .global(true)
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Storage file to use instead of the default state directory (also: DEVBOX_HOME)"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .global(true)
+                .default_value("table")
+                .value_parser(PossibleValuesParser::new(OutputFormat::NAMES))
+                .help("Output format for list, fp list, status and inspect"),
+        )
         .subcommand(
             Command::new("init")
                 .about("Initialize devbox with available containers from SSH server")
@@ -24,6 +60,12 @@ fn main() {
                     Arg::new("sshname")
                         .required(true)
                         .help("SSH name for the remote machine"),
+                )
+                .arg(
+                    Arg::new("runtime")
+                        .long("runtime")
+                        .value_parser(PossibleValuesParser::new(["docker", "podman", "nerdctl"]))
+                        .help("Container runtime on the remote machine (auto-detected if omitted)"),
                 ),
         )
         .subcommand(
@@ -36,17 +78,124 @@ fn main() {
                 )
                 .arg(
                     Arg::new("container")
+                        .help("Specify which container to connect to (defaults to default_container from config)"),
+                )
+                .arg(ensure_running_arg())
+                .after_help(
+                    "Runs remote_script when configured. Otherwise runs /usr/local/bin/docker_tmux.sh \
+                     if the host has it and no attach_command is configured, else attaches with \
+                     attach_command (default: tmux new -A -s devbox nvim).",
+                ),
+        )
+        .subcommand(
+            Command::new("exec")
+                .about("Run a command in a container, passing through stdin and the exit code")
+                .arg(
+                    Arg::new("sshname")
                         .required(true)
-                        .help("Specify which container to connect to"),
+                        .help("SSH name for the remote machine"),
+                )
+                .arg(
+                    Arg::new("container")
+                        .help("Specify which container to run in (defaults to default_container from config)"),
+                )
+                .arg(ensure_running_arg())
+                .arg(
+                    Arg::new("command")
+                        .required(true)
+                        .num_args(1..)
+                        .last(true)
+                        .help("Command and arguments to run, after --"),
+                ),
+        )
+        .subcommand(
+            Command::new("shell")
+                .about("Open an interactive shell in a container over ssh, exiting with its status")
+                .arg(
+                    Arg::new("sshname")
+                        .required(true)
+                        .help("SSH name for the remote machine"),
+                )
+                .arg(
+                    Arg::new("container")
+                        .help("Specify which container to open the shell in (defaults to default_container from config)"),
                 ),
         )
         .subcommand(
             Command::new("list")
-                .about("List stored container names for all SSH hosts"),
+                .about("List stored containers for all SSH hosts")
+                .arg(
+                    Arg::new("sshname")
+                        .help("Only list containers on this SSH host"),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .long("filter")
+                        .value_name("KEY=VALUE")
+                        .action(ArgAction::Append)
+                        .value_parser(filter::parse_filter)
+                        .help("Only list matching containers: status=running, label=KEY, label=KEY=VALUE or image=PATTERN (repeatable)"),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_name("PATTERN")
+                        .help("Only list containers whose name matches a glob like 'api-*'"),
+                )
+                .arg(
+                    Arg::new("live")
+                        .long("live")
+                        .action(ArgAction::SetTrue)
+                        .help("Query every host now instead of showing what 'init' stored, marking containers that appeared or disappeared since"),
+                ),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Show runtime, shared connection, containers and forwards per SSH host")
+                .arg(
+                    Arg::new("sshname")
+                        .help("SSH name for the remote machine (all known hosts if omitted)"),
+                ),
+        )
+        .subcommand(
+            Command::new("inspect")
+                .about("Show a container's current metadata, fetched from the host")
+                .arg(
+                    Arg::new("sshname")
+                        .required(true)
+                        .help("SSH name for the remote machine"),
+                )
+                .arg(
+                    Arg::new("container")
+                        .required(true)
+                        .help("Specify which container to inspect"),
+                ),
+        )
+        .subcommand(lifecycle_command("start", "Start a stopped container"))
+        .subcommand(lifecycle_command("stop", "Stop a running container"))
+        .subcommand(lifecycle_command("restart", "Restart a container"))
+        .subcommand(
+            lifecycle_command("rm", "Remove a container").arg(
+                Arg::new("force")
+                    .long("force")
+                    .short('f')
+                    .action(ArgAction::SetTrue)
+                    .help("Remove the container even if it is running"),
+            ),
+        )
+        .subcommand(
+            Command::new("disconnect")
+                .about("Close the shared SSH connection to a host, or to every known host")
+                .arg(
+                    Arg::new("sshname")
+                        .help("SSH name for the remote machine (all known hosts if omitted)"),
+                ),
         )
         .subcommand(
             Command::new("fp")
                 .about("Forward port from a specific container to the calling host")
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
                 .arg(
                     Arg::new("sshname")
                         .required(true)
@@ -58,189 +207,1142 @@ fn main() {
                         .help("Specify which container to forward the port from"),
                 )
                 .arg(
-                    Arg::new("src")
+                    Arg::new("ports")
+                        .required_unless_present("all")
+                        .conflicts_with("all")
+                        .num_args(1..)
+                        .value_parser(forward::parse_port_spec)
+                        .help("Ports to forward as LOCAL:CONTAINER, PORT (same on both sides) or ranges like 8080-8090:8080-8090; either side may be a socket like unix:/run/app.sock. A container socket outside a mounted volume is reached through /proc, which needs root on the host"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                        .help("Forward every port the container exposes or publishes to the same local port"),
+                )
+                .arg(
+                    Arg::new("detach")
+                        .long("detach")
+                        .short('d')
+                        .action(ArgAction::SetTrue)
+                        .help("Run the tunnel in the background; manage it with 'fp list' and 'fp stop'"),
+                )
+                .arg(ensure_running_arg())
+                .arg(
+                    Arg::new("supervise")
+                        .long("supervise")
+                        .conflicts_with("detach")
+                        .action(ArgAction::SetTrue)
+                        .help("Restart the tunnel whenever it drops, following the container if it is recreated"),
+                )
+                .subcommand(
+                    Command::new("list").about("List port forwards running in the background"),
+                )
+                .subcommand(
+                    Command::new("stop")
+                        .about("Stop a background port forward")
+                        .arg(
+                            Arg::new("id")
+                                .required(true)
+                                .help("Forward id from 'fp list', or 'all'"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("rfp")
+                .about("Make a port on the calling host reachable from inside a container")
+                .after_help("The host's sshd must allow binding non-loopback addresses (GatewayPorts clientspecified).")
+                .arg(
+                    Arg::new("sshname")
+                        .required(true)
+                        .help("SSH name for the remote machine"),
+                )
+                .arg(
+                    Arg::new("container")
                         .required(true)
-                        .help("Specify the destination port on the client side"),
+                        .help("Specify which container should reach the port"),
                 )
                 .arg(
-                    Arg::new("dest")
+                    Arg::new("container_port")
                         .required(true)
-                        .help("Specify the source port on the container side"),
+                        .value_parser(clap::value_parser!(u16).range(1..))
+                        .help("Port the container connects to on its gateway address"),
+                )
+                .arg(
+                    Arg::new("local_port")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u16).range(1..))
+                        .help("Port on the calling host to expose"),
+                ),
+        )
+        .subcommand(
+            Command::new("proxy")
+                .about("Start a SOCKS proxy into the remote machine's container network")
+                .arg(
+                    Arg::new("sshname")
+                        .required(true)
+                        .help("SSH name for the remote machine"),
+                )
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .default_value("1080")
+                        .value_parser(clap::value_parser!(u16).range(1..))
+                        .help("Local port for the SOCKS proxy"),
                 ),
         )
         .get_matches();
 
+    if let Some(path) = matches.get_one::<PathBuf>("storage") {
+        paths::set_storage_override(path.clone());
+    }
+
+    if let Err(e) = run(&matches) {
+        eprintln!("Error: {}", e);
+        process::exit(e.exit_code());
+    }
+}
+
+fn run(matches: &clap::ArgMatches) -> Result<()> {
+    let config = Config::load().map_err(|e| DevboxError::Config(e.to_string()))?;
+    let format = matches
+        .get_one::<String>("output")
+        .and_then(|name| OutputFormat::from_name(name))
+        .unwrap_or_default();
+
     match matches.subcommand() {
         Some(("init", sub_m)) => {
             let sshname = sub_m.get_one::<String>("sshname").unwrap();
-            match initialize_containers(sshname) {
-                Ok(_) => println!("Initialized containers for SSH name: {}", sshname),
-                Err(e) => eprintln!("Error initializing containers: {}", e),
-            }
+            let host = config.host(sshname);
+            let runtime = sub_m
+                .get_one::<String>("runtime")
+                .and_then(|name| RuntimeKind::from_name(name))
+                .or(host.runtime);
+            let kind = initialize_containers(&host, runtime)?;
+            println!(
+                "Initialized {} containers for SSH name: {}",
+                kind.name(),
+                sshname
+            );
+            Ok(())
         }
         Some(("nvim", sub_m)) => {
             let sshname = sub_m.get_one::<String>("sshname").unwrap();
-            let container = sub_m.get_one::<String>("container").unwrap();
-            let ssh_command = format!(
-                "mosh {} -- /usr/local/bin/docker_tmux.sh {}",
-                sshname, container
-            );
-            execute_command(&ssh_command, &format!("Neovim in container '{}'", container));
-        }
-        Some(("list", _)) => {
-            match load_storage() {
-                Ok(storage) => {
-                    println!("Stored containers by SSH name:");
-                    for (sshname, containers) in storage.containers.iter() {
-                        println!("- {}: {:?}", sshname, containers);
-                    }
+            let host = config.host(sshname);
+            let container = container_arg(sub_m, &host)?;
+            if sub_m.get_flag("ensure_running") {
+                ensure_running(&host, container)?;
+            }
+            let executor = RemoteExecutor::new(&host);
+            // A configured remote script keeps working; otherwise devbox attaches itself.
+            let command = match &host.remote_script {
+                Some(script) => vec![script.clone(), container.to_string()],
+                None if host.configured_attach_command(container).is_none()
+                    && has_legacy_script(&executor) =>
+                {
+                    vec![LEGACY_REMOTE_SCRIPT.to_string(), container.to_string()]
                 }
-                Err(e) => eprintln!("Error loading storage: {}", e),
+                None => runtime_for(&host).exec(container, &host.attach_command(container), true),
+            };
+            report(
+                executor.interactive(&command),
+                &format!("Neovim in container '{}'", container),
+            )
+        }
+        Some(("exec", sub_m)) => {
+            let sshname = sub_m.get_one::<String>("sshname").unwrap();
+            let host = config.host(sshname);
+            let container = container_arg(sub_m, &host)?;
+            if sub_m.get_flag("ensure_running") {
+                ensure_running(&host, container)?;
             }
+            let command: Vec<String> = sub_m
+                .get_many::<String>("command")
+                .unwrap()
+                .cloned()
+                .collect();
+            // Only ask for a TTY when attached to one, so pipes and redirects stay byte-clean.
+            // Plain ssh is used either way because mosh does not report the remote exit status.
+            let tty = io::stdin().is_terminal() && io::stdout().is_terminal();
+            let argv = runtime_for(&host).exec(container, &command, tty);
+            exit_with_status(
+                RemoteExecutor::new(&host).run(&argv, tty).status(),
+                &format!("exec in container '{}'", container),
+            )
+        }
+        Some(("shell", sub_m)) => {
+            let sshname = sub_m.get_one::<String>("sshname").unwrap();
+            let host = config.host(sshname);
+            let container = container_arg(sub_m, &host)?;
+            let argv = runtime_for(&host).exec(container, &shell_command(), true);
+            // Over plain ssh like `exec`, so the shell's exit status comes back.
+            exit_with_status(
+                RemoteExecutor::new(&host).run(&argv, true).status(),
+                &format!("shell in container '{}'", container),
+            )
         }
-        Some(("fp", sub_m)) => {
+        Some(("list", sub_m)) => list_containers(&config, sub_m, format),
+        Some(("status", sub_m)) => show_status(&config, sub_m.get_one::<String>("sshname"), format),
+        Some(("inspect", sub_m)) => {
             let sshname = sub_m.get_one::<String>("sshname").unwrap();
             let container = sub_m.get_one::<String>("container").unwrap();
-            let src_port = sub_m.get_one::<String>("src").unwrap();
-            let dest_port = sub_m.get_one::<String>("dest").unwrap();
-
-            match fetch_container_ip(sshname, container) {
-                Ok(container_ip) => {
-                    // Use src_port as the local port and dest_port as the container's port
-                    let ssh_command = format!(
-                        "ssh -L {0}:{1}:{2} {3} -N",
-                        src_port, container_ip, dest_port, sshname
-                    );
-                    execute_command(&ssh_command, &format!("Port forwarding for container '{}'", container));
+            show_inspect(&config.host(sshname), container, format)
+        }
+        Some(("disconnect", sub_m)) => {
+            let sshnames: Vec<String> = match sub_m.get_one::<String>("sshname") {
+                Some(sshname) => vec![sshname.clone()],
+                None => known_hosts(&config),
+            };
+            let mut failed = 0;
+            for sshname in sshnames {
+                let host = config.host(&sshname);
+                if !host.multiplex {
+                    println!("Connection sharing is disabled for '{}'", sshname);
+                    continue;
                 }
-                Err(e) => eprintln!("Failed to fetch IP for container '{}': {}", container, e),
+                match RemoteExecutor::new(&host).disconnect() {
+                    Ok(true) => println!("Closed shared connection to '{}'", sshname),
+                    Ok(false) => println!("No shared connection open to '{}'", sshname),
+                    Err(e) => {
+                        eprintln!("Failed to close connection to '{}': {}", sshname, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(DevboxError::SshFailed(format!(
+                    "{} shared connection(s) could not be closed",
+                    failed
+                )));
             }
+            Ok(())
         }
-        _ => eprintln!("No valid subcommand was provided"),
+        Some((action @ ("start" | "stop" | "restart" | "rm"), sub_m)) => {
+            let sshname = sub_m.get_one::<String>("sshname").unwrap();
+            let container = sub_m.get_one::<String>("container").unwrap();
+            let force = action == "rm" && sub_m.get_flag("force");
+            change_container(&config.host(sshname), container, action, force)
+        }
+        Some(("fp", sub_m)) => match sub_m.subcommand() {
+            Some(("list", _)) => list_forwards(format),
+            Some(("stop", stop_m)) => stop_forwards(stop_m.get_one::<String>("id").unwrap()),
+            _ => forward_port(&config, sub_m),
+        },
+        Some(("rfp", sub_m)) => reverse_forward_port(&config, sub_m),
+        Some(("proxy", sub_m)) => {
+            let sshname = sub_m.get_one::<String>("sshname").unwrap();
+            let host = config.host(sshname);
+            let port = *sub_m.get_one::<u16>("port").unwrap();
+            print_container_ips(&host);
+            println!("SOCKS proxy listening on 127.0.0.1:{}", port);
+            report(
+                RemoteExecutor::new(&host).proxy(port),
+                &format!("SOCKS proxy to '{}'", sshname),
+            )
+        }
+        _ => Err(DevboxError::Usage(
+            "No valid subcommand was provided".to_string(),
+        )),
     }
 }
 
-fn fetch_container_ip(sshname: &str, container: &str) -> io::Result<String> {
-    // Step 1: Fetch the network name(s) for the container
-    let network_output = ShellCommand::new("ssh")
-        .arg(sshname)
-        .arg(format!(
-            "docker inspect {} | jq -r '.[0].NetworkSettings.Networks | keys[0]'",
+fn forward_port(config: &Config, sub_m: &clap::ArgMatches) -> Result<()> {
+    let sshname = sub_m.get_one::<String>("sshname").unwrap();
+    let host = config.host(sshname);
+    let container = sub_m.get_one::<String>("container").unwrap();
+    if sub_m.get_flag("ensure_running") {
+        ensure_running(&host, container)?;
+    }
+
+    let mappings: Vec<PortMapping> = if sub_m.get_flag("all") {
+        exposed_port_mappings(&fetch_container_ip(&host, container)?.ports, container)
+    } else {
+        sub_m
+            .get_many::<Vec<PortMapping>>("ports")
+            .unwrap()
+            .flatten()
+            .cloned()
+            .collect()
+    };
+    if mappings.is_empty() {
+        return Err(DevboxError::Failed(format!(
+            "No ports to forward for container '{}'",
+            container
+        )));
+    }
+    let tunnel = tunnel_for(&host, container, &mappings, false)?;
+
+    let executor = RemoteExecutor::new(&host);
+    if sub_m.get_flag("supervise") {
+        println!(
+            "Supervising forward of {} from container '{}' (local:container)",
+            Mappings(&mappings),
             container
+        );
+        let resolve = || tunnel_for(&host, container, &mappings, true);
+        forward::supervise(&executor, tunnel, resolve).map_err(|e| {
+            DevboxError::Failed(format!(
+                "Failed to supervise forward for '{}': {}",
+                container, e
+            ))
+        })?;
+        return Ok(());
+    }
+    if !sub_m.get_flag("detach") {
+        println!(
+            "Forwarding {} from container '{}' (local:container)",
+            Mappings(&mappings),
+            container
+        );
+        return report(
+            executor.forward(&tunnel),
+            &format!("Port forwarding for container '{}'", container),
+        );
+    }
+
+    let pid = forward::spawn_detached(&executor, &tunnel).map_err(|e| {
+        DevboxError::SshFailed(format!(
+            "Failed to start background forward for '{}': {}",
+            container, e
         ))
-        .output()?;
+    })?;
+    let id =
+        update_storage(|storage| forward::record(storage, pid, sshname, container, &mappings))?;
+    println!(
+        "Forwarding {} from container '{}' in the background (id {})",
+        Mappings(&mappings),
+        container,
+        id
+    );
+    Ok(())
+}
 
-    if !network_output.status.success() {
-        eprintln!(
-            "Error fetching network name: {}",
-            String::from_utf8_lossy(&network_output.stderr)
+fn reverse_forward_port(config: &Config, sub_m: &clap::ArgMatches) -> Result<()> {
+    let sshname = sub_m.get_one::<String>("sshname").unwrap();
+    let host = config.host(sshname);
+    let container = sub_m.get_one::<String>("container").unwrap();
+    let container_port = *sub_m.get_one::<u16>("container_port").unwrap();
+    let local_port = *sub_m.get_one::<u16>("local_port").unwrap();
+
+    // The gateway is the host's address on the container's network, so a
+    // listener bound there is reachable from inside the container.
+    let gateway = fetch_container_ip(&host, container)?.gateway;
+    if gateway.is_empty() {
+        return Err(DevboxError::NoNetwork(format!(
+            "Container '{}' has no gateway address to bind to",
+            container
+        )));
+    }
+
+    println!(
+        "Inside container '{}', connect to {}:{} to reach local port {}",
+        container, gateway, container_port, local_port
+    );
+    report(
+        RemoteExecutor::new(&host).reverse_forward(container_port, local_port, &gateway),
+        &format!("Reverse port forwarding for container '{}'", container),
+    )
+}
+
+/// Prints the current address of every container stored for the host, so they
+/// can be reached through the proxy.
+fn print_container_ips(host: &HostSettings) {
+    let storage = load_storage().ok();
+    let names: Vec<String> = storage
+        .as_ref()
+        .and_then(|storage| storage.containers.get(&host.sshname).cloned())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|info| info.name)
+        .collect();
+    if names.is_empty() {
+        println!(
+            "No containers stored for '{}'; run 'devbox init {}' to list them here",
+            host.sshname, host.sshname
         );
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to fetch container network name over SSH",
-        ));
+        return;
     }
 
-    let network_name = String::from_utf8_lossy(&network_output.stdout).trim().to_string();
-    if network_name.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "No network found for the specified container",
-        ));
+    // One inspect for all of them. It exits non-zero when any is gone but
+    // still prints the rest, so its status is ignored.
+    let kind = host
+        .runtime
+        .or_else(|| storage?.runtimes.get(&host.sshname).copied())
+        .unwrap_or_default();
+    let live: Vec<ContainerInfo> = RemoteExecutor::new(host)
+        .output(&kind.runtime().inspect(&names))
+        .ok()
+        .and_then(|output| inspect::parse_all(&output.stdout).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .map(ContainerInfo::from)
+        .collect();
+
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    println!("Containers on '{}':", host.sshname);
+    for name in names {
+        let ip = live
+            .iter()
+            .find(|info| info.name == name)
+            .and_then(ContainerInfo::ip)
+            .unwrap_or("-");
+        println!("  {:width$}  {}", name, ip, width = width);
     }
+}
 
-    // Step 2: Fetch the container IP address on the determined network
-    let ip_output = ShellCommand::new("ssh")
-        .arg(sshname)
-        .arg(format!(
-            "docker inspect {} | jq -r '.[0].NetworkSettings.Networks[\"{}\"].IPAddress'",
-            container, network_name
-        ))
-        .output()?;
+/// Same-port mappings for the container's exposed ports, leaving out any
+/// that can't be bound on this machine.
+fn exposed_port_mappings(ports: &[u16], container: &str) -> Vec<PortMapping> {
+    if ports.is_empty() {
+        println!("Container '{}' does not expose any TCP ports", container);
+    }
+    ports
+        .iter()
+        .filter(|&&port| match local_port_unavailable(port) {
+            Some(reason) => {
+                println!("Skipping port {}: {}", port, reason);
+                false
+            }
+            None => true,
+        })
+        .map(|&port| PortMapping {
+            local: Endpoint::Port(port),
+            container: Endpoint::Port(port),
+        })
+        .collect()
+}
+
+/// Why `port` can't be forwarded from this machine, if it can't. ssh listens
+/// on both loopback addresses, so both are tried.
+fn local_port_unavailable(port: u16) -> Option<String> {
+    let addresses: [IpAddr; 2] = [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+    for address in addresses {
+        match TcpListener::bind((address, port)) {
+            Ok(_) => {}
+            // The machine has no IPv6 loopback.
+            Err(e) if e.kind() == io::ErrorKind::AddrNotAvailable => {}
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                return Some(format!("already in use on {}", address));
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                return Some("binding it needs elevated privileges".to_string());
+            }
+            Err(e) => return Some(e.to_string()),
+        }
+    }
+    None
+}
 
-    if !ip_output.status.success() {
-        eprintln!(
-            "Error fetching IP address: {}",
-            String::from_utf8_lossy(&ip_output.stderr)
+/// A container with the host it belongs to, as printed by `list` and `inspect`.
+#[derive(Serialize)]
+struct ListedContainer {
+    host: String,
+    #[serde(flatten)]
+    info: ContainerInfo,
+    /// Seconds since the container started, while running.
+    uptime: Option<u64>,
+    /// With `list --live`: `new` or `gone` relative to what `init` stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<&'static str>,
+}
+
+impl ListedContainer {
+    fn new(host: &str, info: ContainerInfo, change: Option<&'static str>) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        ListedContainer {
+            host: host.to_string(),
+            uptime: info.uptime(now).filter(|_| change != Some("gone")),
+            info,
+            change,
+        }
+    }
+
+    /// The container's state, or `gone` if it has disappeared.
+    fn status(&self) -> &str {
+        match self.change {
+            Some("gone") => "gone",
+            _ => &self.info.status,
+        }
+    }
+}
+
+fn list_containers(config: &Config, sub_m: &clap::ArgMatches, format: OutputFormat) -> Result<()> {
+    let storage = load_storage()?;
+    let mut hosts: Vec<&String> = match sub_m.get_one::<String>("sshname") {
+        Some(sshname) => vec![sshname],
+        None => storage.containers.keys().collect(),
+    };
+    hosts.sort();
+
+    // Hosts are slow to answer, so they are all asked at once.
+    let current: Vec<Option<Vec<ContainerInfo>>> = if sub_m.get_flag("live") {
+        thread::scope(|scope| {
+            let queries: Vec<_> = hosts
+                .iter()
+                .map(|sshname| {
+                    let storage = &storage;
+                    scope.spawn(move || {
+                        let host = config.host(sshname);
+                        let kind = host
+                            .runtime
+                            .or_else(|| storage.runtimes.get(*sshname).copied())
+                            .unwrap_or_default();
+                        fetch_containers(&RemoteExecutor::new(&host), kind)
+                    })
+                })
+                .collect();
+            queries
+                .into_iter()
+                .zip(&hosts)
+                .map(|(query, sshname)| match query.join() {
+                    Ok(Ok(containers)) => Some(containers),
+                    Ok(Err(e)) => {
+                        eprintln!(
+                            "Could not query '{}', showing stored containers: {}",
+                            sshname, e
+                        );
+                        None
+                    }
+                    Err(_) => None,
+                })
+                .collect()
+        })
+    } else {
+        hosts.iter().map(|_| None).collect()
+    };
+
+    let mut listed = Vec::new();
+    for (host, current) in hosts.into_iter().zip(current) {
+        let stored = storage.containers.get(host).map_or(&[][..], Vec::as_slice);
+        let Some(current) = current else {
+            listed.extend(
+                stored
+                    .iter()
+                    .map(|info| ListedContainer::new(host, info.clone(), None)),
+            );
+            continue;
+        };
+        let gone: Vec<&ContainerInfo> = stored
+            .iter()
+            .filter(|info| !current.iter().any(|c| c.name == info.name))
+            .collect();
+        for info in current {
+            let new = !stored.iter().any(|s| s.name == info.name);
+            listed.push(ListedContainer::new(host, info, new.then_some("new")));
+        }
+        listed.extend(
+            gone.into_iter()
+                .map(|info| ListedContainer::new(host, info.clone(), Some("gone"))),
         );
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to fetch container IP address over SSH",
-        ));
     }
 
-    let ip_address = String::from_utf8_lossy(&ip_output.stdout).trim().to_string();
-    if ip_address.is_empty() {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "No IP address assigned to the container on the detected network",
+    let filters: Vec<&Filter> = sub_m
+        .get_many::<Filter>("filter")
+        .into_iter()
+        .flatten()
+        .collect();
+    let name = sub_m.get_one::<String>("name");
+    listed.retain(|entry| {
+        name.is_none_or(|pattern| glob::matches(pattern, &entry.info.name))
+            && filters
+                .iter()
+                .all(|filter| filter.matches(&entry.info, entry.status()))
+    });
+
+    let mut table = Table::new(&["HOST", "CONTAINER", "IMAGE", "STATE", "UPTIME", "IP"]);
+    for entry in &listed {
+        let state = match entry.change {
+            Some("new") => format!("{} (new)", entry.info.status),
+            _ => entry.status().to_string(),
+        };
+        table.row(vec![
+            entry.host.clone(),
+            entry.info.name.clone(),
+            entry.info.image.clone(),
+            state,
+            entry.uptime.map_or("-".to_string(), output::duration),
+            entry.info.ip().unwrap_or("-").to_string(),
+        ]);
+    }
+    output::print(format, &listed, &table)
+}
+
+/// One row of `status`.
+#[derive(Serialize)]
+struct HostStatus {
+    host: String,
+    runtime: Option<RuntimeKind>,
+    /// `open`, `closed`, or `disabled` when multiplexing is off.
+    connection: &'static str,
+    containers: usize,
+    running: usize,
+    forwards: usize,
+}
+
+fn show_status(config: &Config, sshname: Option<&String>, format: OutputFormat) -> Result<()> {
+    let hosts = match sshname {
+        Some(sshname) => vec![sshname.clone()],
+        None => known_hosts(config),
+    };
+    // Stale forwards are pruned first so the counts only include live tunnels.
+    let mut statuses: Vec<HostStatus> = update_storage(|storage| {
+        forward::prune(storage);
+        hosts
+            .iter()
+            .map(|host| {
+                let containers = storage.containers.get(host).map_or(&[][..], Vec::as_slice);
+                HostStatus {
+                    host: host.clone(),
+                    runtime: storage.runtimes.get(host).copied(),
+                    connection: "disabled",
+                    containers: containers.len(),
+                    running: containers.iter().filter(|c| c.status == "running").count(),
+                    forwards: storage
+                        .forwards
+                        .iter()
+                        .filter(|f| &f.sshname == host)
+                        .count(),
+                }
+            })
+            .collect()
+    })?;
+
+    let mut table = Table::new(&[
+        "HOST",
+        "RUNTIME",
+        "CONNECTION",
+        "CONTAINERS",
+        "RUNNING",
+        "FORWARDS",
+    ]);
+    for status in &mut statuses {
+        let host = config.host(&status.host);
+        if host.multiplex {
+            let open = RemoteExecutor::new(&host).connected().unwrap_or(false);
+            status.connection = if open { "open" } else { "closed" };
+        }
+        table.row(vec![
+            status.host.clone(),
+            status.runtime.map_or("-", RuntimeKind::name).to_string(),
+            status.connection.to_string(),
+            status.containers.to_string(),
+            status.running.to_string(),
+            status.forwards.to_string(),
+        ]);
+    }
+    output::print(format, &statuses, &table)
+}
+
+fn show_inspect(host: &HostSettings, container: &str, format: OutputFormat) -> Result<()> {
+    let info = ContainerInfo::from(inspect_container(host, container)?);
+    let join = |items: Vec<String>| items.join(", ");
+
+    let mut table = Table::new(&["FIELD", "VALUE"]);
+    let fields = [
+        ("host", host.sshname.clone()),
+        ("name", info.name.clone()),
+        ("id", info.id.clone()),
+        ("image", info.image.clone()),
+        ("status", info.status.clone()),
+        ("created", info.created.clone()),
+        (
+            "networks",
+            join(
+                info.networks
+                    .iter()
+                    .map(|(name, ip)| format!("{}={}", name, ip))
+                    .collect(),
+            ),
+        ),
+        ("ports", join(info.ports.clone())),
+        (
+            "labels",
+            join(
+                info.labels
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect(),
+            ),
+        ),
+    ];
+    for (field, value) in fields {
+        table.row(vec![field.to_string(), value]);
+    }
+    output::print(
+        format,
+        &ListedContainer::new(&host.sshname, info, None),
+        &table,
+    )
+}
+
+fn list_forwards(format: OutputFormat) -> Result<()> {
+    let forwards = update_storage(|storage| {
+        forward::prune(storage);
+        storage.forwards.clone()
+    })?;
+
+    let mut table = Table::new(&["ID", "HOST", "CONTAINER", "PORTS", "PID"]);
+    for f in &forwards {
+        table.row(vec![
+            f.id.to_string(),
+            f.sshname.clone(),
+            f.container.clone(),
+            Mappings(&f.ports).to_string(),
+            f.pid.to_string(),
+        ]);
+    }
+    output::print(format, &forwards, &table)
+}
+
+fn stop_forwards(id: &str) -> Result<()> {
+    let selected: Option<u32> = match id {
+        "all" => None,
+        id => Some(
+            id.parse()
+                .map_err(|_| DevboxError::Usage(format!("Invalid forward id '{}'", id)))?,
+        ),
+    };
+
+    let (stopped, failed) = update_storage(|storage| {
+        // A stale record's pid may belong to an unrelated process by now.
+        forward::prune(storage);
+        let mut stopped = Vec::new();
+        let mut failed = 0;
+        storage.forwards.retain(|f| {
+            if selected.is_some_and(|id| id != f.id) {
+                return true;
+            }
+            match forward::stop(f) {
+                Ok(()) => {
+                    stopped.push(f.id);
+                    false
+                }
+                Err(e) => {
+                    eprintln!("Failed to stop forward {}: {}", f.id, e);
+                    failed += 1;
+                    true
+                }
+            }
+        });
+        (stopped, failed)
+    })?;
+
+    if stopped.is_empty() && failed == 0 {
+        if let Some(id) = selected {
+            return Err(DevboxError::Usage(format!(
+                "No running port forward with id {}",
+                id
+            )));
+        }
+        println!("No port forwards to stop.");
+    }
+    for id in stopped {
+        println!("Stopped port forward {}", id);
+    }
+    if failed > 0 {
+        return Err(DevboxError::Failed(format!(
+            "{} port forward(s) could not be stopped",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+/// IP cached at `init`, falling back to asking the host when none was recorded.
+fn container_ip(host: &HostSettings, container: &str) -> Result<String> {
+    let cached = load_storage().ok().and_then(|storage| {
+        storage
+            .container(&host.sshname, container)?
+            .ip()
+            .map(str::to_string)
+    });
+    match cached {
+        Some(ip) => Ok(ip),
+        None => fetch_container_ip(host, container).map(|endpoint| endpoint.ip),
+    }
+}
+
+/// Resolves where `mappings` point on the SSH host. Container-side sockets
+/// always need a fresh inspect; otherwise the cached IP is used unless `fresh`.
+fn tunnel_for(
+    host: &HostSettings,
+    container: &str,
+    mappings: &[PortMapping],
+    fresh: bool,
+) -> Result<Tunnel> {
+    let has_sockets = mappings
+        .iter()
+        .any(|mapping| matches!(mapping.container, Endpoint::Unix(_)));
+    if !fresh && !has_sockets {
+        return Ok(Tunnel {
+            target_host: container_ip(host, container)?,
+            mappings: mappings.to_vec(),
+        });
+    }
+
+    let endpoint = fetch_container_ip(host, container)?;
+    let mappings = mappings
+        .iter()
+        .map(|mapping| match &mapping.container {
+            Endpoint::Unix(path) => {
+                let host_path = match endpoint.inspect.mounted_path(path) {
+                    Some(host_path) => host_path,
+                    None => proc_socket_path(host, container, &endpoint.inspect, path)?,
+                };
+                Ok(PortMapping {
+                    local: mapping.local.clone(),
+                    container: Endpoint::Unix(host_path),
+                })
+            }
+            Endpoint::Port(_) => Ok(mapping.clone()),
+        })
+        .collect::<Result<_>>()?;
+    Ok(Tunnel {
+        target_host: endpoint.ip,
+        mappings,
+    })
+}
+
+/// Reaches a socket that is not on a mount through the container's root under
+/// `/proc`, checking first that the ssh user may enter it.
+fn proc_socket_path(
+    host: &HostSettings,
+    container: &str,
+    inspect: &ContainerInspect,
+    path: &str,
+) -> Result<String> {
+    let proc_path = inspect.proc_path(path).ok_or_else(|| {
+        DevboxError::Failed(format!(
+            "Socket {} is unreachable while container '{}' is not running",
+            path, container
         ))
+    })?;
+    let test = ["test", "-S", proc_path.as_str()].map(String::from);
+    if !RemoteExecutor::new(host).output(&test)?.success() {
+        return Err(DevboxError::Failed(format!(
+            "Socket {} is not on a mounted volume and {} is not accessible on '{}'. \
+             Reaching it through /proc needs root on the host; mount its directory \
+             as a volume or connect as root",
+            path, proc_path, host.sshname
+        )));
+    }
+    Ok(proc_path)
+}
+
+/// Where a container can be reached from its host.
+struct ContainerEndpoint {
+    ip: String,
+    /// The host's address on the container's network.
+    gateway: String,
+    /// TCP ports the container exposes or publishes.
+    ports: Vec<u16>,
+    inspect: ContainerInspect,
+}
+
+fn fetch_container_ip(host: &HostSettings, container: &str) -> Result<ContainerEndpoint> {
+    let inspect = inspect_container(host, container)?;
+    let (_, endpoint) = inspect.first_network().ok_or_else(|| {
+        DevboxError::NoNetwork(format!("Container '{}' is not on any network", container))
+    })?;
+
+    if endpoint.ip_address.is_empty() {
+        Err(DevboxError::NoNetwork(format!(
+            "Container '{}' has no IP address on network '{}'",
+            container,
+            inspect.first_network().map_or("", |(name, _)| name)
+        )))
     } else {
-        Ok(ip_address)
+        Ok(ContainerEndpoint {
+            ip: endpoint.ip_address.clone(),
+            gateway: endpoint.gateway.clone(),
+            ports: inspect.tcp_ports(),
+            inspect,
+        })
     }
 }
 
-fn initialize_containers(sshname: &str) -> io::Result<()> {
-    let output = ShellCommand::new("ssh")
-        .arg(sshname)
-        .arg("docker ps -a --format {{.Names}}")
-        .output()?;
+/// Fresh `inspect` output for one container.
+fn inspect_container(host: &HostSettings, container: &str) -> Result<ContainerInspect> {
+    let output =
+        RemoteExecutor::new(host).output(&runtime_for(host).inspect(&[container.to_string()]))?;
 
-    if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
+    if !output.success() {
+        return Err(container_failure(
+            &output,
+            container,
+            "Failed to inspect container over SSH",
+        ));
+    }
+
+    inspect::parse(&output.stdout)
+}
+
+/// Classifies a failed runtime command about `container`.
+fn container_failure(output: &RemoteOutput, container: &str, context: &str) -> DevboxError {
+    // Docker says "No such object" or "No such container", Podman and nerdctl "no such container".
+    if output.code != Some(255) && output.stderr.to_lowercase().contains("no such") {
+        return DevboxError::ContainerNotFound(container.to_string());
+    }
+    DevboxError::remote(output, context)
+}
+
+/// Runs `start`, `stop`, `restart` or `rm` on the container and updates what
+/// `init` stored about it.
+fn change_container(host: &HostSettings, container: &str, action: &str, force: bool) -> Result<()> {
+    let runtime = runtime_for(host);
+    let (argv, done) = match action {
+        "start" => (runtime.start(container), "Started"),
+        "stop" => (runtime.stop(container), "Stopped"),
+        "restart" => (runtime.restart(container), "Restarted"),
+        _ => (runtime.rm(container, force), "Removed"),
+    };
+    let output = RemoteExecutor::new(host).output(&argv)?;
+    if !output.success() {
+        return Err(container_failure(
+            &output,
+            container,
+            &format!("Failed to {} container '{}'", action, container),
+        ));
+    }
+
+    if action == "rm" {
+        update_storage(|storage| {
+            if let Some(containers) = storage.containers.get_mut(&host.sshname) {
+                containers.retain(|info| info.name != *container);
+            }
+        })?;
+    } else {
+        refresh_stored(host, container)?;
+    }
+    println!("{} container '{}'", done, container);
+    Ok(())
+}
+
+/// Starts the container unless it is already running. Progress goes to stderr
+/// so `exec` output stays clean.
+fn ensure_running(host: &HostSettings, container: &str) -> Result<()> {
+    let inspect = inspect_container(host, container)?;
+    if inspect.state.status == "running" {
+        return Ok(());
+    }
+
+    eprintln!(
+        "Starting container '{}' ({})",
+        container, inspect.state.status
+    );
+    let output = RemoteExecutor::new(host).output(&runtime_for(host).start(container))?;
+    if !output.success() {
+        return Err(container_failure(
+            &output,
+            container,
+            &format!("Failed to start container '{}'", container),
+        ));
+    }
+    // A started container usually gets a new IP, which fp would otherwise read from the cache.
+    refresh_stored(host, container)
+}
+
+/// Replaces the stored metadata for a container the host already has stored.
+fn refresh_stored(host: &HostSettings, container: &str) -> Result<()> {
+    let info = ContainerInfo::from(inspect_container(host, container)?);
+    update_storage(|storage| {
+        let stored = storage
+            .containers
+            .get_mut(&host.sshname)
+            .and_then(|containers| {
+                containers
+                    .iter_mut()
+                    .find(|stored| stored.name == info.name)
+            });
+        if let Some(stored) = stored {
+            *stored = info;
+        }
+    })
+}
+
+fn initialize_containers(host: &HostSettings, runtime: Option<RuntimeKind>) -> Result<RuntimeKind> {
+    let executor = RemoteExecutor::new(host);
+    let kind = match runtime {
+        Some(kind) => kind,
+        None => detect_runtime(&executor, &host.sshname)?,
+    };
+    let containers = fetch_containers(&executor, kind)?;
+
+    update_storage(|storage| {
+        storage.containers.insert(host.sshname.clone(), containers);
+        storage.runtimes.insert(host.sshname.clone(), kind);
+    })?;
+    Ok(kind)
+}
+
+/// Every container on the host, running or not, with its current metadata.
+fn fetch_containers(executor: &RemoteExecutor, kind: RuntimeKind) -> Result<Vec<ContainerInfo>> {
+    let output = executor.output(&kind.runtime().list())?;
+
+    if !output.success() {
+        return Err(DevboxError::remote(
+            &output,
             "Failed to fetch container names over SSH",
         ));
     }
 
-    let container_names: Vec<String> = String::from_utf8_lossy(&output.stdout)
+    let container_names: Vec<String> = output
+        .stdout
         .lines()
         .map(|line| line.trim().to_string())
+        .filter(|name| !name.is_empty())
         .collect();
 
-    let mut storage = load_storage().unwrap_or_else(|_| DevboxStorage {
-        containers: HashMap::new(),
-    });
-    storage.containers.insert(sshname.to_string(), container_names);
+    if container_names.is_empty() {
+        return Ok(Vec::new());
+    }
+    inspect_containers(executor, kind, &container_names)
+}
 
-    save_storage(&storage)
+fn inspect_containers(
+    executor: &RemoteExecutor,
+    kind: RuntimeKind,
+    names: &[String],
+) -> Result<Vec<ContainerInfo>> {
+    let output = executor.output(&kind.runtime().inspect(names))?;
+
+    if !output.success() {
+        return Err(DevboxError::remote(
+            &output,
+            "Failed to inspect containers over SSH",
+        ));
+    }
+
+    Ok(inspect::parse_all(&output.stdout)?
+        .into_iter()
+        .map(ContainerInfo::from)
+        .collect())
 }
 
-fn load_storage() -> io::Result<DevboxStorage> {
-    let path = shellexpand::tilde(STORAGE_FILE).to_string();
-    if !Path::new(&path).exists() {
-        return Ok(DevboxStorage {
-            containers: HashMap::new(),
-        });
+fn detect_runtime(executor: &RemoteExecutor, sshname: &str) -> Result<RuntimeKind> {
+    let output = executor.output(&runtime::detect_command())?;
+
+    if !output.success() {
+        return Err(DevboxError::remote(
+            &output,
+            "Failed to detect container runtime over SSH",
+        ));
+    }
+
+    RuntimeKind::from_name(output.stdout.trim())
+        .ok_or_else(|| DevboxError::RuntimeMissing(sshname.to_string()))
+}
+
+/// Runtime configured for the host, else the one recorded at `init`, falling
+/// back to Docker for hosts initialized before runtimes were tracked.
+fn runtime_for(host: &HostSettings) -> Box<dyn runtime::ContainerRuntime> {
+    host.runtime
+        .or_else(|| {
+            load_storage()
+                .ok()
+                .and_then(|storage| storage.runtimes.get(&host.sshname).copied())
+        })
+        .unwrap_or_default()
+        .runtime()
+}
+
+/// Hosts from storage and config, sorted and without duplicates.
+fn known_hosts(config: &Config) -> Vec<String> {
+    let mut hosts: Vec<String> = config.hosts.keys().cloned().collect();
+    if let Ok(storage) = load_storage() {
+        hosts.extend(storage.containers.into_keys());
     }
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    serde_json::from_reader(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+/// Whether the host still has the script `nvim` ran before attach commands existed.
+fn has_legacy_script(executor: &RemoteExecutor) -> bool {
+    let test = ["test", "-x", LEGACY_REMOTE_SCRIPT].map(String::from);
+    executor.output(&test).is_ok_and(|output| output.success())
 }
 
-fn save_storage(storage: &DevboxStorage) -> io::Result<()> {
-    let path = shellexpand::tilde(STORAGE_FILE).to_string();
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(path)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, storage).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+/// Container named on the command line, else the host's configured default.
+fn container_arg<'a>(sub_m: &'a clap::ArgMatches, host: &'a HostSettings) -> Result<&'a String> {
+    sub_m
+        .get_one::<String>("container")
+        .or(host.default_container.as_ref())
+        .ok_or_else(|| {
+            DevboxError::Usage(format!(
+                "No container given and no default_container configured for '{}'",
+                host.sshname
+            ))
+        })
 }
 
-fn execute_command(command: &str, context: &str) {
-    let status = ShellCommand::new("sh")
-        .arg("-c")
-        .arg(command)
-        .status()
-        .expect("Failed to execute command");
+/// A `start|stop|restart|rm <sshname> <container>` subcommand.
+fn lifecycle_command(name: &'static str, about: &'static str) -> Command {
+    Command::new(name)
+        .about(about)
+        .arg(
+            Arg::new("sshname")
+                .required(true)
+                .help("SSH name for the remote machine"),
+        )
+        .arg(
+            Arg::new("container")
+                .required(true)
+                .help("Specify which container to act on"),
+        )
+}
 
-    if status.success() {
-        println!("Successfully executed {} command.", context);
-    } else {
-        eprintln!("Failed to execute {} command.", context);
+fn ensure_running_arg() -> Arg {
+    Arg::new("ensure_running")
+        .long("ensure-running")
+        .action(ArgAction::SetTrue)
+        .help("Start the container first if it is not running")
+}
+
+/// Prefers bash when the image has it.
+fn shell_command() -> Vec<String> {
+    [
+        "sh",
+        "-c",
+        "command -v bash >/dev/null 2>&1 && exec bash || exec sh",
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+/// Runs `command` and exits devbox with its exit code. Only returns if it could not be started.
+fn exit_with_status(status: io::Result<ExitStatus>, context: &str) -> Result<()> {
+    match status {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => Err(DevboxError::Failed(format!(
+            "Failed to execute {} command: {}",
+            context, e
+        ))),
     }
 }
 
+/// Turns the status of a foreground ssh into a result. ssh exits with 255
+/// when it could not reach the host, as in [`DevboxError::remote`].
+fn report(result: io::Result<ExitStatus>, context: &str) -> Result<()> {
+    match result {
+        Ok(status) if status.success() => {
+            println!("Successfully executed {} command.", context);
+            Ok(())
+        }
+        Ok(status) if status.code() == Some(255) => Err(DevboxError::SshFailed(format!(
+            "{} command could not reach the host",
+            context
+        ))),
+        Ok(_) => Err(DevboxError::Failed(format!(
+            "Failed to execute {} command.",
+            context
+        ))),
+        Err(e) => Err(DevboxError::Failed(format!(
+            "Failed to execute {} command: {}",
+            context, e
+        ))),
+    }
+}
@@ -0,0 +1,518 @@
+//! In-process SSH client built on libssh2, used instead of spawning `ssh` when
+//! devbox is built with the `native-ssh` feature. Host aliases are resolved
+//! from `~/.ssh/config`, host keys are checked against `~/.ssh/known_hosts`,
+//! and authentication tries the SSH agent before the configured identity files.
+
+use crate::forward::{Endpoint, Tunnel};
+use crate::glob;
+use crate::remote::RemoteOutput;
+use crate::transport::SshTarget;
+use ssh2::{Channel, CheckResult, KnownHostFileKind, Session};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_IDENTITIES: [&str; 3] = ["~/.ssh/id_ed25519", "~/.ssh/id_ecdsa", "~/.ssh/id_rsa"];
+
+/// Connection parameters for an ssh alias after applying `~/.ssh/config`.
+struct ResolvedHost {
+    hostname: String,
+    port: u16,
+    user: String,
+    identity_files: Vec<PathBuf>,
+}
+
+/// Runs the already-quoted command `line` on the host and captures its output.
+pub fn output(target: &SshTarget, line: &str) -> io::Result<RemoteOutput> {
+    let session = connect(target)?;
+    let mut channel = session.channel_session()?;
+    channel.exec(line)?;
+
+    let (stdout, stderr) = read_output(&channel, &session)?;
+    channel.wait_close()?;
+
+    Ok(RemoteOutput {
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        code: Some(channel.exit_status()?),
+    })
+}
+
+/// Reads stdout and stderr until the command closes them. Both share the
+/// channel's window, so draining one while the other fills up would deadlock.
+fn read_output(channel: &Channel, session: &Session) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    session.set_blocking(false);
+    let mut streams = [
+        (channel.stream(0), Vec::new()),
+        (channel.stderr(), Vec::new()),
+    ];
+    let mut buf = [0u8; 16 * 1024];
+
+    loop {
+        let mut idle = true;
+        for (stream, data) in &mut streams {
+            match stream.read(&mut buf) {
+                Ok(0) => {}
+                Ok(n) => {
+                    data.extend_from_slice(&buf[..n]);
+                    idle = false;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    session.set_blocking(true);
+                    return Err(e);
+                }
+            }
+        }
+        if idle {
+            if channel.eof() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    session.set_blocking(true);
+    let [(_, stdout), (_, stderr)] = streams;
+    Ok((stdout, stderr))
+}
+
+/// Forwards each mapping from `127.0.0.1` to its port on the tunnel's target
+/// host as seen from the SSH host until interrupted. Each local connection gets
+/// its own session. Only TCP ports are supported.
+pub fn forward(target: &SshTarget, tunnel: &Tunnel) -> io::Result<()> {
+    let target_host = tunnel.target_host.as_str();
+    let listeners = tunnel
+        .mappings
+        .iter()
+        .map(|mapping| match (&mapping.local, &mapping.container) {
+            (Endpoint::Port(local), Endpoint::Port(container)) => {
+                Ok((TcpListener::bind(("127.0.0.1", *local))?, *container))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix socket forwarding needs the OpenSSH client",
+            )),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = listeners
+            .into_iter()
+            .map(|(listener, port)| {
+                scope.spawn(move || {
+                    accept(listener, || {
+                        let session = connect(target)?;
+                        let channel = session.channel_direct_tcpip(target_host, port, None)?;
+                        Ok((session, channel))
+                    })
+                })
+            })
+            .collect();
+        handles.into_iter().try_for_each(|handle| {
+            handle
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("Forwarding thread panicked")))
+        })
+    })
+}
+
+/// Serves each connection on `listener` over a channel from `open`. A failed
+/// connection is reported and skipped so the listener keeps forwarding.
+fn accept(
+    listener: TcpListener,
+    open: impl Fn() -> io::Result<(Session, Channel)>,
+) -> io::Result<()> {
+    let port = listener.local_addr()?.port();
+    for local in listener.incoming() {
+        let local = match local {
+            Ok(local) => local,
+            Err(e) => {
+                eprintln!("Failed to accept a connection on port {}: {}", port, e);
+                // Errors like EMFILE repeat immediately; don't spin on them.
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let (session, channel) = match open() {
+            Ok(opened) => opened,
+            Err(e) => {
+                eprintln!("Failed to forward a connection on port {}: {}", port, e);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            if let Err(e) = pump(local, channel, &session) {
+                eprintln!("Forwarded connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn connect(target: &SshTarget) -> io::Result<Session> {
+    if target.jump_host.is_some() {
+        return Err(unsupported("jump_host"));
+    }
+    if !target.ssh_args.is_empty() {
+        return Err(unsupported("ssh_args"));
+    }
+    open_session(&resolve(&target.sshname)?)
+}
+
+fn unsupported(setting: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{setting} is not supported by the native SSH client"),
+    )
+}
+
+fn open_session(host: &ResolvedHost) -> io::Result<Session> {
+    let mut session = Session::new()?;
+    session.set_tcp_stream(TcpStream::connect((host.hostname.as_str(), host.port))?);
+    session.handshake()?;
+    verify_host_key(&session, host)?;
+    authenticate(&session, host)?;
+    Ok(session)
+}
+
+fn verify_host_key(session: &Session, host: &ResolvedHost) -> io::Result<()> {
+    let mut known_hosts = session.known_hosts()?;
+    let file = expand("~/.ssh/known_hosts");
+    if file.exists() {
+        known_hosts.read_file(&file, KnownHostFileKind::OpenSSH)?;
+    }
+
+    let (key, _) = session
+        .host_key()
+        .ok_or_else(|| io::Error::other("Server did not present a host key"))?;
+    match known_hosts.check_port(&host.hostname, host.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Host key for {} is not in {}; connect once with ssh to add it",
+                host.hostname,
+                file.display()
+            ),
+        )),
+        CheckResult::Mismatch => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Host key for {} does not match known_hosts", host.hostname),
+        )),
+        CheckResult::Failure => Err(io::Error::other("Failed to check the host key")),
+    }
+}
+
+fn authenticate(session: &Session, host: &ResolvedHost) -> io::Result<()> {
+    if session.userauth_agent(&host.user).is_ok() {
+        return Ok(());
+    }
+    for identity in host.identity_files.iter().filter(|path| path.exists()) {
+        if session
+            .userauth_pubkey_file(&host.user, None, identity, None)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Authentication failed for {}@{}", host.user, host.hostname),
+    ))
+}
+
+/// Copies bytes both ways between the local socket and the channel until either side closes.
+fn pump(mut local: TcpStream, mut channel: Channel, session: &Session) -> io::Result<()> {
+    local.set_nonblocking(true)?;
+    session.set_blocking(false);
+    let mut buf = [0u8; 16 * 1024];
+
+    loop {
+        let mut idle = true;
+
+        match local.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                write_all(&mut channel, &buf[..n])?;
+                idle = false;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+
+        match channel.read(&mut buf) {
+            Ok(0) if channel.eof() => break,
+            Ok(0) => {}
+            Ok(n) => {
+                write_all(&mut local, &buf[..n])?;
+                idle = false;
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+
+        if idle {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    session.set_blocking(true);
+    channel.close()?;
+    Ok(())
+}
+
+/// `write_all` for non-blocking writers, retrying until everything is written.
+fn write_all(writer: &mut impl Write, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match writer.write(data) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Applies the `HostName`, `Port`, `User` and `IdentityFile` settings from
+/// `~/.ssh/config` to `alias`.
+fn resolve(alias: &str) -> io::Result<ResolvedHost> {
+    let config = fs::read_to_string(expand("~/.ssh/config")).unwrap_or_default();
+    resolve_in(&config, alias)
+}
+
+/// Resolves `alias` against the contents of an ssh config file. As with
+/// OpenSSH, the first value found wins. Settings that would route the
+/// connection elsewhere (`ProxyJump`, `ProxyCommand`, `Include`) are rejected
+/// rather than ignored.
+fn resolve_in(config: &str, alias: &str) -> io::Result<ResolvedHost> {
+    let mut hostname = None;
+    let mut proxy = None;
+    let mut port = None;
+    let mut user = None;
+    let mut identity_files = Vec::new();
+
+    let mut applies = true;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((keyword, value)) => (
+                keyword.to_ascii_lowercase(),
+                value.trim_start_matches(['=', ' ', '\t']).trim(),
+            ),
+            None => continue,
+        };
+
+        match keyword.as_str() {
+            "host" => applies = host_matches(alias, value),
+            // Match blocks need runtime evaluation devbox doesn't do; skip them.
+            "match" => applies = false,
+            _ if !applies => {}
+            "hostname" => {
+                hostname.get_or_insert_with(|| value.replace("%h", alias));
+            }
+            "port" if port.is_none() => port = value.parse().ok(),
+            "user" => {
+                user.get_or_insert_with(|| value.to_string());
+            }
+            "identityfile" => identity_files.push(expand(value)),
+            "proxyjump" => {
+                proxy.get_or_insert(("ProxyJump", value));
+            }
+            "proxycommand" => {
+                proxy.get_or_insert(("ProxyCommand", value));
+            }
+            "include" => return Err(unsupported("Include in ~/.ssh/config")),
+            _ => {}
+        }
+    }
+    if let Some((setting, value)) = proxy {
+        if !value.eq_ignore_ascii_case("none") {
+            return Err(unsupported(setting));
+        }
+    }
+
+    if identity_files.is_empty() {
+        identity_files = DEFAULT_IDENTITIES.iter().map(|path| expand(path)).collect();
+    }
+
+    Ok(ResolvedHost {
+        hostname: hostname.unwrap_or_else(|| alias.to_string()),
+        port: port.unwrap_or(22),
+        user: user
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_else(|| "root".to_string()),
+        identity_files,
+    })
+}
+
+/// Whether `alias` matches a `Host` line's patterns; any matching `!pattern`
+/// excludes it. Like OpenSSH, matching ignores case.
+fn host_matches(alias: &str, patterns: &str) -> bool {
+    let alias = alias.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in patterns.to_ascii_lowercase().split_whitespace() {
+        match pattern.strip_prefix('!') {
+            Some(negated) if glob::matches(negated, &alias) => return false,
+            Some(_) => {}
+            None => matched |= glob::matches(pattern, &alias),
+        }
+    }
+    matched
+}
+
+fn expand(path: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(path).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    const CONFIG: &str = "\
+# comment
+Host *.internal !db.internal
+    User deploy
+    Port 2222
+
+Host dev
+    HostName %h.example.com
+    IdentityFile=/keys/dev
+
+Host dev DEV2
+    HostName ignored.example.com
+    User=admin
+    Port 2200
+
+Match host dev
+    User matched
+
+Host *
+    User fallback
+    IdentityFile /keys/default
+";
+
+    #[test]
+    fn resolve_takes_first_value_per_keyword() {
+        let host = resolve_in(CONFIG, "dev").unwrap();
+        assert_eq!(host.hostname, "dev.example.com");
+        assert_eq!(host.port, 2200);
+        assert_eq!(host.user, "admin");
+        assert_eq!(
+            host.identity_files,
+            [PathBuf::from("/keys/dev"), PathBuf::from("/keys/default")]
+        );
+    }
+
+    #[test]
+    fn resolve_applies_wildcards_and_negations() {
+        let web = resolve_in(CONFIG, "web.internal").unwrap();
+        assert_eq!((web.hostname.as_str(), web.port), ("web.internal", 2222));
+        assert_eq!(web.user, "deploy");
+
+        let db = resolve_in(CONFIG, "db.internal").unwrap();
+        assert_eq!((db.port, db.user.as_str()), (22, "fallback"));
+    }
+
+    #[test]
+    fn resolve_without_config_uses_defaults() {
+        let host = resolve_in("", "box").unwrap();
+        assert_eq!((host.hostname.as_str(), host.port), ("box", 22));
+        assert_eq!(host.identity_files.len(), DEFAULT_IDENTITIES.len());
+    }
+
+    #[test]
+    fn resolve_rejects_settings_it_cannot_honour() {
+        let jump = "Host dev\n    ProxyJump bastion\n";
+        let err = resolve_in(jump, "dev").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(resolve_in(jump, "other").is_ok());
+
+        let command = "Host *\n    ProxyCommand nc %h %p\n";
+        assert!(resolve_in(command, "dev").is_err());
+        assert!(resolve_in("Include config.d/*\n", "dev").is_err());
+
+        let disabled = "Host dev\n    ProxyJump none\nHost *\n    ProxyJump bastion\n";
+        assert!(resolve_in(disabled, "dev").is_ok());
+    }
+
+    #[test]
+    fn host_patterns() {
+        assert!(host_matches("dev", "dev"));
+        assert!(host_matches("DEV", "other dev"));
+        assert!(host_matches("web1.internal", "web?.internal"));
+        assert!(host_matches("anything", "*"));
+        assert!(!host_matches("web10.internal", "web?.internal"));
+        assert!(!host_matches("db.internal", "*.internal !db.internal"));
+        assert!(!host_matches("dev", "!prod"));
+    }
+
+    // None of these tests completes a real SSH handshake: no SSH server is
+    // available to the test suite, so `output`, `forward` and `proxy` are only
+    // exercised up to a failed connection.
+
+    /// Serves `reply` to every connection, standing in for a broken SSH server.
+    fn fake_server(reply: &'static [u8]) -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.write_all(reply);
+            }
+        });
+        port
+    }
+
+    fn local_host(port: u16) -> ResolvedHost {
+        ResolvedHost {
+            hostname: "127.0.0.1".to_string(),
+            port,
+            user: "devbox".to_string(),
+            identity_files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn connecting_to_a_non_ssh_server_fails() {
+        let port = fake_server(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+        assert!(open_session(&local_host(port)).is_err());
+    }
+
+    #[test]
+    fn failed_connection_does_not_stop_the_listener() {
+        let server = fake_server(b"garbage\r\n");
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&attempts);
+        thread::spawn(move || {
+            accept(listener, || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                open_session(&local_host(server)).map(|_| unreachable!())
+            })
+        });
+
+        for expected in 1..=3 {
+            let _client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while attempts.load(Ordering::SeqCst) < expected {
+                assert!(Instant::now() < deadline, "listener stopped accepting");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
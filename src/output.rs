@@ -0,0 +1,108 @@
+use crate::error::{DevboxError, Result};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// How `list`, `fp list`, `status` and `inspect` print their results (the global `--output` flag).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Aligned columns under a header.
+    #[default]
+    Table,
+    /// Tab-separated rows without a header, for fzf and shell pipelines.
+    Plain,
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 4] = ["table", "plain", "json", "yaml"];
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "table" => Some(OutputFormat::Table),
+            "plain" => Some(OutputFormat::Plain),
+            "json" => Some(OutputFormat::Json),
+            "yaml" => Some(OutputFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Rows rendered by the text formats.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Table {
+            headers: headers.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    fn aligned(&self) -> String {
+        let header: Vec<String> = self.headers.iter().map(|h| h.to_string()).collect();
+        let lines: Vec<&Vec<String>> = std::iter::once(&header).chain(&self.rows).collect();
+
+        let mut widths = vec![0; self.headers.len()];
+        for line in &lines {
+            for (width, cell) in widths.iter_mut().zip(line.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let mut text = String::new();
+        for line in lines {
+            let cells: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            text.push_str(cells.join("  ").trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    fn plain(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| format!("{}\n", row.join("\t")))
+            .collect()
+    }
+}
+
+/// Short human form of a duration, like `3d 4h`, `5h 12m` or `45s`.
+pub fn duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, _) => format!("{}m", minutes),
+        (0, _, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+/// Prints `data` as JSON or YAML, or `table` for the text formats.
+pub fn print<T: Serialize + ?Sized>(format: OutputFormat, data: &T, table: &Table) -> Result<()> {
+    let text = match format {
+        OutputFormat::Table => table.aligned(),
+        OutputFormat::Plain => table.plain(),
+        OutputFormat::Json => serde_json::to_string_pretty(data).map_err(unprintable)? + "\n",
+        OutputFormat::Yaml => serde_yaml_ng::to_string(data).map_err(unprintable)?,
+    };
+    // A reader like `head` closing the pipe early is not an error.
+    match io::stdout().lock().write_all(text.as_bytes()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(unprintable(e)),
+        _ => Ok(()),
+    }
+}
+
+fn unprintable(error: impl std::fmt::Display) -> DevboxError {
+    DevboxError::Failed(format!("Failed to print output: {}", error))
+}
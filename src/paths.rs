@@ -0,0 +1,86 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const LEGACY_STORAGE_FILE: &str = "~/.devbox_storage.json";
+const STORAGE_FILE_NAME: &str = "storage.json";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+static STORAGE_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Points storage at `path` for the rest of the process (the `--storage` flag).
+pub fn set_storage_override(path: PathBuf) {
+    let _ = STORAGE_OVERRIDE.set(path);
+}
+
+/// Location of the storage file, in order of precedence: `--storage`,
+/// `$DEVBOX_HOME/storage.json`, then `$XDG_STATE_HOME/devbox/storage.json`.
+pub fn storage_file() -> PathBuf {
+    if let Some(path) = STORAGE_OVERRIDE.get() {
+        return path.clone();
+    }
+    if let Some(home) = devbox_home() {
+        return home.join(STORAGE_FILE_NAME);
+    }
+    state_dir().join(STORAGE_FILE_NAME)
+}
+
+/// Location of the user configuration: `$DEVBOX_HOME/config.toml`, then
+/// `$XDG_CONFIG_HOME/devbox/config.toml`.
+pub fn config_file() -> PathBuf {
+    if let Some(home) = devbox_home() {
+        return home.join(CONFIG_FILE_NAME);
+    }
+    env_dir("XDG_CONFIG_HOME")
+        .unwrap_or_else(|| expand("~/.config"))
+        .join("devbox")
+        .join(CONFIG_FILE_NAME)
+}
+
+/// Directory holding SSH ControlMaster sockets: `$DEVBOX_HOME/control`, then
+/// `$XDG_RUNTIME_DIR/devbox`, falling back to the state directory.
+pub fn control_dir() -> PathBuf {
+    if let Some(home) = devbox_home() {
+        return home.join("control");
+    }
+    match env_dir("XDG_RUNTIME_DIR") {
+        Some(dir) => dir.join("devbox"),
+        None => state_dir().join("control"),
+    }
+}
+
+/// The pre-XDG storage file, when storage lives at its default location and
+/// the legacy file should therefore be moved there.
+pub fn legacy_storage_file() -> Option<PathBuf> {
+    if STORAGE_OVERRIDE.get().is_some() || devbox_home().is_some() {
+        return None;
+    }
+    Some(expand(LEGACY_STORAGE_FILE))
+}
+
+/// `path` with `suffix` appended to its file name, for lock and temp files.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn devbox_home() -> Option<PathBuf> {
+    env_dir("DEVBOX_HOME")
+}
+
+fn state_dir() -> PathBuf {
+    env_dir("XDG_STATE_HOME")
+        .unwrap_or_else(|| expand("~/.local/state"))
+        .join("devbox")
+}
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+fn expand(path: &str) -> PathBuf {
+    PathBuf::from(shellexpand::tilde(path).into_owned())
+}
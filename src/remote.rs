@@ -0,0 +1,353 @@
+use crate::config::HostSettings;
+use crate::error::{DevboxError, Result};
+use crate::forward::Tunnel;
+#[cfg(feature = "native-ssh")]
+use crate::native;
+use crate::transport::{self, Mosh, Ssh, SshClient, SshTarget, Transport, TransportKind};
+use std::io;
+use std::process::{Child, Command as ShellCommand, ExitStatus};
+use std::thread;
+use std::time::Duration;
+
+/// How often and how many times to look for the remote end of a reverse forward.
+const BIND_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const BIND_CHECKS: u32 = 20;
+
+/// Captured result of a command run on the remote host.
+pub struct RemoteOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Remote exit code; `None` if the command was killed by a signal.
+    pub code: Option<i32>,
+}
+
+impl RemoteOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs argv vectors on an SSH host, quoting every argument for the remote
+/// shell so container names and ports are never interpreted as shell syntax.
+pub struct RemoteExecutor {
+    target: SshTarget,
+    transport: TransportKind,
+    client: SshClient,
+}
+
+impl RemoteExecutor {
+    pub fn new(host: &HostSettings) -> Self {
+        RemoteExecutor {
+            target: SshTarget::new(host),
+            transport: host.transport,
+            client: host.ssh_client.effective(),
+        }
+    }
+
+    /// Runs `argv` over ssh and captures its output. Failing to run ssh or to
+    /// connect is an [`DevboxError::SshFailed`]; the command's own status is in the output.
+    pub fn output(&self, argv: &[String]) -> Result<RemoteOutput> {
+        let line = command_line(argv);
+        #[cfg(feature = "native-ssh")]
+        if self.uses_native() {
+            return native::output(&self.target, &line)
+                .map_err(|e| DevboxError::SshFailed(format!("{}: {}", self.target.sshname, e)));
+        }
+        self.capture(&line)
+            .map_err(|e| DevboxError::SshFailed(format!("Could not run ssh: {}", e)))
+    }
+
+    /// Whether commands and forwards go through the in-process client instead of `ssh`.
+    pub fn uses_native(&self) -> bool {
+        self.client == SshClient::Native
+    }
+
+    /// Runs an interactive session for `argv` over the configured transport.
+    /// A mosh session that fails to connect is retried over plain ssh.
+    pub fn interactive(&self, argv: &[String]) -> io::Result<ExitStatus> {
+        let kind = transport::installed(self.transport);
+        let status = kind.transport().interactive(&self.target, argv).status()?;
+        if kind == TransportKind::Mosh && status.code() == Some(Mosh::CONNECT_FAILED) {
+            eprintln!(
+                "mosh could not connect to '{}', retrying over ssh",
+                self.target.sshname
+            );
+            return Ssh.interactive(&self.target, argv).status();
+        }
+        Ok(status)
+    }
+
+    /// `argv` over plain ssh with inherited stdio, so stdin, stdout and the exit
+    /// status pass straight through. `tty` forces a remote pseudo-terminal.
+    pub fn run(&self, argv: &[String], tty: bool) -> ShellCommand {
+        self.target.command(argv, tty)
+    }
+
+    /// Whether a shared ControlMaster connection to the host is open.
+    pub fn connected(&self) -> io::Result<bool> {
+        Ok(self.target.control("check").output()?.status.success())
+    }
+
+    /// Closes the host's shared ControlMaster connection. Returns `false` when none was open.
+    pub fn disconnect(&self) -> io::Result<bool> {
+        let running = self.connected()?;
+        if running && !self.target.control("exit").output()?.status.success() {
+            return Err(io::Error::other("ssh -O exit failed"));
+        }
+        Ok(running)
+    }
+
+    /// Forwards each of the tunnel's mappings in the foreground until
+    /// interrupted. Returns whether the tunnel exited cleanly.
+    pub fn forward(&self, tunnel: &Tunnel) -> io::Result<ExitStatus> {
+        #[cfg(feature = "native-ssh")]
+        if self.uses_native() {
+            native::forward(&self.target, tunnel)?;
+            return Ok(ExitStatus::default());
+        }
+
+        self.forward_command(tunnel, &[]).status()
+    }
+
+    /// A single `ssh -N` carrying one `-L` per mapping, without a remote command.
+    /// `options` are extra ssh options placed before the destination.
+    pub fn forward_command(&self, tunnel: &Tunnel, options: &[&str]) -> ShellCommand {
+        let mut command = self.target.ssh_unshared();
+        command.args(options);
+        if tunnel.has_local_sockets() {
+            // A socket left behind by an earlier tunnel would otherwise block the bind.
+            command.args(["-o", "StreamLocalBindUnlink=yes"]);
+        }
+        for forward in tunnel.ssh_forwards() {
+            command.arg("-L").arg(forward);
+        }
+        command.arg("-N").arg("--").arg(&self.target.sshname);
+        command
+    }
+
+    /// Makes `local_port` reachable on `bind_host:remote_port` on the SSH host,
+    /// in the foreground until interrupted. Returns whether the tunnel exited cleanly.
+    pub fn reverse_forward(
+        &self,
+        remote_port: u16,
+        local_port: u16,
+        bind_host: &str,
+    ) -> io::Result<ExitStatus> {
+        if self.uses_native() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Reverse forwarding needs the OpenSSH client",
+            ));
+        }
+
+        // Without this ssh keeps running even though nothing is listening remotely.
+        let mut child = self
+            .target
+            .ssh_unshared()
+            .args(["-o", "ExitOnForwardFailure=yes"])
+            .arg("-R")
+            .arg(format!(
+                "{}:{}:localhost:{}",
+                bind_host, remote_port, local_port
+            ))
+            .arg("-N")
+            .arg("--")
+            .arg(&self.target.sshname)
+            .spawn()?;
+
+        if let Err(e) = self.await_remote_bind(&mut child, bind_host, remote_port) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        child.wait()
+    }
+
+    /// Waits until sshd listens on `bind_host:port`. With `GatewayPorts no` it
+    /// binds to loopback instead without telling the client, and containers
+    /// can't reach the forward.
+    fn await_remote_bind(&self, child: &mut Child, bind_host: &str, port: u16) -> io::Result<()> {
+        let ss = ["ss".to_string(), "-ltn".to_string()];
+        for _ in 0..BIND_CHECKS {
+            thread::sleep(BIND_CHECK_INTERVAL);
+            if child.try_wait()?.is_some() {
+                // ssh gave up on its own; its exit status says why.
+                return Ok(());
+            }
+            let output = self
+                .output(&ss)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            if !output.success() {
+                return Err(io::Error::other(
+                    "could not list listening sockets on the host with `ss`",
+                ));
+            }
+            let bound = listening_hosts(&output.stdout, port);
+            if bound
+                .iter()
+                .any(|host| *host == bind_host || matches!(*host, "0.0.0.0" | "*" | "[::]"))
+            {
+                return Ok(());
+            }
+            if !bound.is_empty() {
+                return Err(io::Error::other(format!(
+                    "sshd bound port {} to {} instead of {}; set GatewayPorts clientspecified in the host's sshd_config",
+                    port,
+                    bound.join(", "),
+                    bind_host
+                )));
+            }
+        }
+        Err(io::Error::other(format!(
+            "nothing is listening on {}:{} on the host",
+            bind_host, port
+        )))
+    }
+
+    /// Runs a SOCKS proxy on local `port` that connects out from the SSH host,
+    /// in the foreground until interrupted. Returns whether it exited cleanly.
+    pub fn proxy(&self, port: u16) -> io::Result<ExitStatus> {
+        if self.uses_native() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The SOCKS proxy needs the OpenSSH client",
+            ));
+        }
+
+        self.target
+            .ssh_unshared()
+            .args(["-o", "ExitOnForwardFailure=yes"])
+            .arg("-D")
+            .arg(format!("127.0.0.1:{}", port))
+            .arg("-N")
+            .arg("--")
+            .arg(&self.target.sshname)
+            .status()
+    }
+
+    fn capture(&self, line: &str) -> io::Result<RemoteOutput> {
+        let output = self
+            .target
+            .ssh()
+            .arg("--")
+            .arg(&self.target.sshname)
+            .arg(line)
+            .output()?;
+
+        Ok(RemoteOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            code: output.status.code(),
+        })
+    }
+}
+
+/// The addresses `ss -ltn` reports listening on `port`, without the port.
+fn listening_hosts(ss: &str, port: u16) -> Vec<&str> {
+    let suffix = format!(":{}", port);
+    ss.lines()
+        .filter_map(|line| line.split_whitespace().nth(3))
+        .filter_map(|local| local.strip_suffix(suffix.as_str()))
+        .collect()
+}
+
+/// Joins `argv` into a single command line for a POSIX remote shell.
+pub fn command_line(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Single-quotes `arg` unless it consists only of characters the shell
+/// never treats specially.
+pub fn quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:@%+,".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn plain_words_are_left_alone() {
+        assert_eq!(quote("docker"), "docker");
+        assert_eq!(quote("/usr/local/bin/nvim"), "/usr/local/bin/nvim");
+        assert_eq!(quote("user@host:8080"), "user@host:8080");
+    }
+
+    #[test]
+    fn special_arguments_are_quoted() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("two words"), "'two words'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("a;b"), "'a;b'");
+        assert_eq!(quote("$(id)"), "'$(id)'");
+        assert_eq!(quote("line\nbreak"), "'line\nbreak'");
+        assert_eq!(quote("=cmd"), "'=cmd'");
+        assert_eq!(quote("KEY=value"), "'KEY=value'");
+        assert_eq!(quote("~"), "'~'");
+        assert_eq!(quote("~root/x"), "'~root/x'");
+    }
+
+    #[test]
+    fn listening_hosts_reads_local_addresses() {
+        let ss = "\
+State  Recv-Q Send-Q Local Address:Port  Peer Address:Port Process
+LISTEN 0      128        127.0.0.1:8080       0.0.0.0:*
+LISTEN 0      128       172.17.0.1:18080      0.0.0.0:*
+LISTEN 0      128            [::1]:8080          [::]:*
+LISTEN 0      4096         0.0.0.0:22         0.0.0.0:*
+";
+        assert_eq!(listening_hosts(ss, 8080), ["127.0.0.1", "[::1]"]);
+        assert_eq!(listening_hosts(ss, 22), ["0.0.0.0"]);
+        assert!(listening_hosts(ss, 80).is_empty());
+    }
+
+    #[test]
+    fn command_line_joins_quoted_arguments() {
+        assert_eq!(
+            command_line(&args(&["docker", "exec", "api", "sh", "-c", "echo $HOME"])),
+            "docker exec api sh -c 'echo $HOME'"
+        );
+        assert_eq!(command_line(&[]), "");
+    }
+
+    #[test]
+    fn shell_sees_the_original_arguments() {
+        let original = args(&[
+            "",
+            "it's",
+            "a;b",
+            "$(id)",
+            "`id`",
+            "two words",
+            "line\nbreak",
+            "=cmd",
+            "~",
+            "*",
+            "\\",
+        ]);
+        let script = format!("printf '%s\\0' {}", command_line(&original));
+        let output = Command::new("sh").arg("-c").arg(script).output().unwrap();
+        let printed: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .split_terminator('\0')
+            .map(String::from)
+            .collect();
+        assert_eq!(printed, original);
+    }
+}
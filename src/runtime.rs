@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+
+/// Container runtimes devbox knows how to drive on a remote host.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeKind {
+    #[default]
+    Docker,
+    Podman,
+    Nerdctl,
+}
+
+impl RuntimeKind {
+    /// Detection order used by `init` when no runtime is given explicitly.
+    pub const ALL: [RuntimeKind; 3] = [
+        RuntimeKind::Docker,
+        RuntimeKind::Podman,
+        RuntimeKind::Nerdctl,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RuntimeKind::Docker => "docker",
+            RuntimeKind::Podman => "podman",
+            RuntimeKind::Nerdctl => "nerdctl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn runtime(self) -> Box<dyn ContainerRuntime> {
+        match self {
+            RuntimeKind::Docker => Box::new(Docker),
+            RuntimeKind::Podman => Box::new(Podman),
+            RuntimeKind::Nerdctl => Box::new(Nerdctl),
+        }
+    }
+}
+
+/// Builds the remote argv for each container operation. The default
+/// implementations follow the Docker CLI, which Podman and nerdctl mirror.
+pub trait ContainerRuntime {
+    /// Name of the CLI binary on the remote host.
+    fn binary(&self) -> &'static str;
+
+    fn list(&self) -> Vec<String> {
+        argv(self.binary(), &["ps", "-a", "--format", "{{.Names}}"])
+    }
+
+    fn inspect(&self, containers: &[String]) -> Vec<String> {
+        let mut args = argv(self.binary(), &["inspect"]);
+        args.extend(containers.iter().cloned());
+        args
+    }
+
+    /// Runs `command` in `container` with stdin attached, allocating a TTY when `tty` is set.
+    fn exec(&self, container: &str, command: &[String], tty: bool) -> Vec<String> {
+        let mut args = argv(self.binary(), &["exec", if tty { "-it" } else { "-i" }]);
+        args.push(container.to_string());
+        args.extend(command.iter().cloned());
+        args
+    }
+
+    fn start(&self, container: &str) -> Vec<String> {
+        argv(self.binary(), &["start", container])
+    }
+
+    fn stop(&self, container: &str) -> Vec<String> {
+        argv(self.binary(), &["stop", container])
+    }
+
+    fn restart(&self, container: &str) -> Vec<String> {
+        argv(self.binary(), &["restart", container])
+    }
+
+    /// Removes `container`; `force` also removes it while running.
+    fn rm(&self, container: &str, force: bool) -> Vec<String> {
+        let mut args = argv(self.binary(), &["rm"]);
+        if force {
+            args.push("-f".to_string());
+        }
+        args.push(container.to_string());
+        args
+    }
+}
+
+pub struct Docker;
+
+impl ContainerRuntime for Docker {
+    fn binary(&self) -> &'static str {
+        "docker"
+    }
+}
+
+pub struct Podman;
+
+impl ContainerRuntime for Podman {
+    fn binary(&self) -> &'static str {
+        "podman"
+    }
+}
+
+pub struct Nerdctl;
+
+impl ContainerRuntime for Nerdctl {
+    fn binary(&self) -> &'static str {
+        "nerdctl"
+    }
+}
+
+/// Remote argv that prints the first runtime binary found on the remote PATH,
+/// or nothing when there is none. It always exits 0, so a failure means ssh failed.
+pub fn detect_command() -> Vec<String> {
+    let candidates: Vec<&str> = RuntimeKind::ALL.iter().map(|kind| kind.name()).collect();
+    let script = format!(
+        "for r in {}; do command -v \"$r\" >/dev/null 2>&1 && echo \"$r\" && break; done; true",
+        candidates.join(" ")
+    );
+    argv("sh", &["-c", &script])
+}
+
+fn argv(binary: &str, args: &[&str]) -> Vec<String> {
+    std::iter::once(binary)
+        .chain(args.iter().copied())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::{Command, Output};
+
+    /// Runs the detection script with only `binaries` on PATH.
+    fn detect_with(binaries: &[&str]) -> Output {
+        let dir = std::env::temp_dir().join(format!(
+            "devbox-detect-{}-{}",
+            std::process::id(),
+            binaries.join("-")
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for binary in binaries {
+            let path = dir.join(binary);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let argv = detect_command();
+        let output = Command::new("/bin/sh")
+            .args(&argv[1..])
+            .env("PATH", &dir)
+            .output()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        output
+    }
+
+    #[test]
+    fn detection_prints_the_first_runtime_found() {
+        let output = detect_with(&["nerdctl", "podman"]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "podman\n");
+    }
+
+    #[test]
+    fn detection_without_a_runtime_succeeds_with_no_output() {
+        let output = detect_with(&[]);
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+}
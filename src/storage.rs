@@ -0,0 +1,414 @@
+use crate::error::{DevboxError, Result};
+use crate::forward::PortMapping;
+use crate::inspect::{self, ContainerInspect};
+use crate::paths;
+use crate::runtime::RuntimeKind;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Schema version written by this build. Bump it together with a new entry in `MIGRATIONS`.
+const STORAGE_VERSION: u64 = 2;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+const MIGRATIONS: [fn(&mut Value) -> Result<()>; STORAGE_VERSION as usize] =
+    [migrate_v0_to_v1, migrate_v1_to_v2];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DevboxStorage {
+    pub version: u64,
+    pub containers: HashMap<String, Vec<ContainerInfo>>, // Maps SSH names to container lists
+    #[serde(default)]
+    pub runtimes: HashMap<String, RuntimeKind>, // Maps SSH names to their container runtime
+    #[serde(default)]
+    pub forwards: Vec<ForwardRecord>, // Background tunnels started with `fp --detach`
+}
+
+/// A detached `fp` tunnel, tracked so it can be listed and stopped later.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForwardRecord {
+    pub id: u32,
+    pub pid: u32,
+    pub sshname: String,
+    pub container: String,
+    pub ports: Vec<PortMapping>,
+    pub started: u64, // Seconds since the Unix epoch
+    /// Start time of the ssh process as `ps` reports it, so a reused pid is never mistaken for the tunnel.
+    #[serde(default)]
+    pub process_started: String,
+}
+
+/// Container metadata captured from the runtime at `init`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContainerInfo {
+    pub name: String,
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub image: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub created: String,
+    #[serde(default)]
+    pub started: String,
+    #[serde(default)]
+    pub networks: BTreeMap<String, String>, // Maps network names to the container's IP on it
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl ContainerInfo {
+    /// IP on the first network, matching what a fresh `fetch_container_ip` would return.
+    pub fn ip(&self) -> Option<&str> {
+        self.networks
+            .values()
+            .next()
+            .map(String::as_str)
+            .filter(|ip| !ip.is_empty())
+    }
+
+    /// Seconds since the container started, if it was running when inspected.
+    pub fn uptime(&self, now: u64) -> Option<u64> {
+        if self.status != "running" {
+            return None;
+        }
+        now.checked_sub(inspect::parse_timestamp(&self.started)?)
+    }
+}
+
+impl From<ContainerInspect> for ContainerInfo {
+    fn from(inspect: ContainerInspect) -> Self {
+        ContainerInfo {
+            // Docker prefixes names with '/', Podman does not.
+            name: inspect.name.trim_start_matches('/').to_string(),
+            id: inspect.id,
+            image: inspect.config.image,
+            status: inspect.state.status,
+            created: inspect.created,
+            started: inspect.state.started_at,
+            networks: inspect
+                .network_settings
+                .networks
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(network, endpoint)| (network, endpoint.ip_address))
+                .collect(),
+            ports: inspect
+                .config
+                .exposed_ports
+                .unwrap_or_default()
+                .into_keys()
+                .collect(),
+            labels: inspect.config.labels.unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for DevboxStorage {
+    fn default() -> Self {
+        DevboxStorage {
+            version: STORAGE_VERSION,
+            containers: HashMap::new(),
+            runtimes: HashMap::new(),
+            forwards: Vec::new(),
+        }
+    }
+}
+
+impl DevboxStorage {
+    pub fn container(&self, sshname: &str, container: &str) -> Option<&ContainerInfo> {
+        self.containers
+            .get(sshname)?
+            .iter()
+            .find(|info| info.name == container)
+    }
+}
+
+/// Reads the storage file. A legacy or older file is upgraded on disk first,
+/// under the same lock `update_storage` takes.
+pub fn load_storage() -> Result<DevboxStorage> {
+    let path = paths::storage_file();
+    if path.exists() || !legacy_storage_exists() {
+        let (storage, found) = read_storage(&path)?;
+        if found == STORAGE_VERSION {
+            return Ok(storage);
+        }
+    }
+    let _lock = lock_storage(&path).map_err(io_failed(&path))?;
+    upgrade_storage(&path)
+}
+
+/// Loads the storage, applies `update` and saves the result while holding an
+/// exclusive lock, so concurrent devbox runs don't overwrite each other's changes.
+pub fn update_storage<T>(update: impl FnOnce(&mut DevboxStorage) -> T) -> Result<T> {
+    let path = paths::storage_file();
+    let _lock = lock_storage(&path).map_err(io_failed(&path))?;
+    let mut storage = upgrade_storage(&path)?;
+    let result = update(&mut storage);
+    save_storage(&storage).map_err(io_failed(&path))?;
+    Ok(result)
+}
+
+/// Takes the exclusive lock on `storage.json.lock`, released when the file is dropped.
+fn lock_storage(path: &Path) -> io::Result<File> {
+    create_parent_dir(path)?;
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(paths::with_suffix(path, ".lock"))?;
+    lock.lock()?;
+    Ok(lock)
+}
+
+/// Moves a legacy file into place and rewrites an older version, keeping a
+/// backup of the original. Callers must hold the lock.
+fn upgrade_storage(path: &Path) -> Result<DevboxStorage> {
+    if !path.exists() && legacy_storage_exists() {
+        if let Some(legacy) = paths::legacy_storage_file() {
+            move_legacy_storage(&legacy, path).map_err(io_failed(&legacy))?;
+        }
+    }
+
+    let (storage, found) = read_storage(path)?;
+    if found < STORAGE_VERSION {
+        let backup = paths::with_suffix(path, &format!(".v{}.bak", found));
+        fs::copy(path, &backup).map_err(io_failed(&backup))?;
+        save_storage(&storage).map_err(io_failed(path))?;
+        eprintln!(
+            "Upgraded storage file to version {} (previous copy saved to {})",
+            STORAGE_VERSION,
+            backup.display()
+        );
+    }
+    Ok(storage)
+}
+
+/// Parses and migrates the file in memory, returning the version found on disk.
+fn read_storage(path: &Path) -> Result<(DevboxStorage, u64)> {
+    if !path.exists() {
+        return Ok((DevboxStorage::default(), STORAGE_VERSION));
+    }
+
+    let file = File::open(path).map_err(io_failed(path))?;
+    let reader = BufReader::new(file);
+    let document: Value = serde_json::from_reader(reader)
+        .map_err(|e| corrupt(format!("{}: {}", path.display(), e)))?;
+    let (document, found) = migrate(document)?;
+
+    let storage = serde_json::from_value(document)
+        .map_err(|e| corrupt(format!("{}: {}", path.display(), e)))?;
+    Ok((storage, found))
+}
+
+/// Brings a document of any known version up to `STORAGE_VERSION`.
+fn migrate(mut document: Value) -> Result<(Value, u64)> {
+    // Files written before the schema was versioned have no `version` field.
+    let found = document.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if found > STORAGE_VERSION {
+        return Err(corrupt(format!(
+            "Storage file version {} is newer than this devbox supports ({})",
+            found, STORAGE_VERSION
+        )));
+    }
+
+    for migrate in &MIGRATIONS[found as usize..] {
+        migrate(&mut document)?;
+    }
+    document["version"] = json!(STORAGE_VERSION);
+    Ok((document, found))
+}
+
+fn legacy_storage_exists() -> bool {
+    paths::legacy_storage_file().is_some_and(|legacy| legacy.exists())
+}
+
+/// Writes the storage to a temporary file next to the real one and renames it
+/// into place, so a crash mid-write never leaves a truncated file behind.
+pub fn save_storage(storage: &DevboxStorage) -> io::Result<()> {
+    let path = paths::storage_file();
+    create_parent_dir(&path)?;
+    let temp_path = paths::with_suffix(&path, &format!(".tmp.{}", std::process::id()));
+
+    let result = write_storage(&temp_path, storage).and_then(|()| fs::rename(&temp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn write_storage(path: &Path, storage: &DevboxStorage) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, storage).map_err(io::Error::other)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+/// Moves `~/.devbox_storage.json` from before storage followed the XDG layout.
+fn move_legacy_storage(legacy: &Path, path: &Path) -> io::Result<()> {
+    create_parent_dir(path)?;
+    // rename fails across filesystems, e.g. a separately mounted state directory.
+    if fs::rename(legacy, path).is_err() {
+        fs::copy(legacy, path)?;
+        fs::remove_file(legacy)?;
+    }
+    eprintln!(
+        "Moved storage file from {} to {}",
+        legacy.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn create_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+/// Version 0 stored bare container names per host; wrap each in a metadata record.
+fn migrate_v0_to_v1(document: &mut Value) -> Result<()> {
+    let hosts = document
+        .get_mut("containers")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| corrupt("Storage file has no containers map"))?;
+
+    for containers in hosts.values_mut() {
+        let entries = containers
+            .as_array_mut()
+            .ok_or_else(|| corrupt("Container list is not an array"))?;
+        for entry in entries.iter_mut() {
+            if let Value::String(name) = entry {
+                *entry = json!({ "name": name, "status": "unknown" });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Version 1 forwards held a single `local_port`/`container_port` string pair.
+fn migrate_v1_to_v2(document: &mut Value) -> Result<()> {
+    let Some(forwards) = document.get_mut("forwards").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
+
+    for forward in forwards.iter_mut() {
+        let Some(record) = forward.as_object_mut() else {
+            continue;
+        };
+        let port = |record: &mut serde_json::Map<String, Value>, key: &str| {
+            record
+                .remove(key)
+                .and_then(|port| port.as_str()?.parse::<u16>().ok())
+        };
+        let ports = match (port(record, "local_port"), port(record, "container_port")) {
+            (Some(local), Some(container)) => json!([{ "local": local, "container": container }]),
+            _ => json!([]),
+        };
+        record.insert("ports".to_string(), ports);
+    }
+    Ok(())
+}
+
+/// Reports a failed storage file operation along with the file involved.
+fn io_failed(path: &Path) -> impl FnOnce(io::Error) -> DevboxError + '_ {
+    move |e| DevboxError::Failed(format!("{}: {}", path.display(), e))
+}
+
+fn corrupt(message: impl Into<String>) -> DevboxError {
+    DevboxError::StorageCorrupt(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v0_bare_names_become_records() {
+        let document = json!({ "containers": { "dev": ["api", "db"] } });
+        let (document, found) = migrate(document).unwrap();
+        assert_eq!(found, 0);
+
+        let storage: DevboxStorage = serde_json::from_value(document).unwrap();
+        assert_eq!(storage.version, STORAGE_VERSION);
+        let names: Vec<&str> = storage.containers["dev"]
+            .iter()
+            .map(|info| info.name.as_str())
+            .collect();
+        assert_eq!(names, ["api", "db"]);
+        assert_eq!(storage.containers["dev"][0].status, "unknown");
+        assert!(storage.forwards.is_empty());
+    }
+
+    #[test]
+    fn v1_forward_ports_become_mappings() {
+        let document = json!({
+            "version": 1,
+            "containers": {},
+            "forwards": [
+                {
+                    "id": 3,
+                    "pid": 4242,
+                    "sshname": "dev",
+                    "container": "api",
+                    "local_port": "8080",
+                    "container_port": "80",
+                    "started": 1700000000
+                },
+                {
+                    "id": 4,
+                    "pid": 4243,
+                    "sshname": "dev",
+                    "container": "api",
+                    "local_port": "not a port",
+                    "container_port": "80",
+                    "started": 1700000000
+                }
+            ]
+        });
+        let (document, found) = migrate(document).unwrap();
+        assert_eq!(found, 1);
+
+        let storage: DevboxStorage = serde_json::from_value(document).unwrap();
+        let forward = &storage.forwards[0];
+        assert_eq!((forward.id, forward.pid), (3, 4242));
+        assert_eq!(forward.ports[0].local.to_string(), "8080");
+        assert_eq!(forward.ports[0].container.to_string(), "80");
+        assert!(storage.forwards[1].ports.is_empty());
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        let document = json!({ "version": STORAGE_VERSION, "containers": {} });
+        let (migrated, found) = migrate(document.clone()).unwrap();
+        assert_eq!(found, STORAGE_VERSION);
+        assert_eq!(migrated, document);
+    }
+
+    #[test]
+    fn newer_and_malformed_documents_are_corrupt() {
+        let newer = json!({ "version": STORAGE_VERSION + 1, "containers": {} });
+        assert!(matches!(
+            migrate(newer),
+            Err(DevboxError::StorageCorrupt(_))
+        ));
+
+        let no_containers = json!({ "version": 0 });
+        assert!(matches!(
+            migrate(no_containers),
+            Err(DevboxError::StorageCorrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,240 @@
+use crate::config::HostSettings;
+use crate::paths;
+use crate::remote::command_line;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command as ShellCommand;
+
+/// How long an idle ControlMaster connection is kept open.
+const CONTROL_PERSIST: &str = "10m";
+
+/// How interactive sessions reach the host.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Mosh,
+    Ssh,
+    Et,
+}
+
+impl TransportKind {
+    pub fn transport(self) -> Box<dyn Transport> {
+        match self {
+            TransportKind::Mosh => Box::new(Mosh),
+            TransportKind::Ssh => Box::new(Ssh),
+            TransportKind::Et => Box::new(EternalTerminal),
+        }
+    }
+}
+
+/// Which SSH implementation runs non-interactive commands and port forwards.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SshClient {
+    /// The system `ssh` binary.
+    #[default]
+    Openssh,
+    /// The in-process client from the `native-ssh` feature.
+    Native,
+}
+
+impl SshClient {
+    /// The client to actually use: native when configured or when `ssh` is not
+    /// installed, provided devbox was built with `native-ssh`.
+    pub fn effective(self) -> SshClient {
+        if cfg!(feature = "native-ssh") {
+            if self == SshClient::Openssh && !on_path("ssh") {
+                return SshClient::Native;
+            }
+            return self;
+        }
+        if self == SshClient::Native {
+            eprintln!("devbox was built without the native-ssh feature, using ssh");
+        }
+        SshClient::Openssh
+    }
+}
+
+/// The SSH destination and options every transport starts its connection with.
+pub struct SshTarget {
+    pub sshname: String,
+    pub jump_host: Option<String>,
+    pub ssh_args: Vec<String>,
+    /// ControlPath template shared by every ssh devbox runs against this host,
+    /// or `None` when multiplexing is disabled.
+    pub control_path: Option<PathBuf>,
+}
+
+impl SshTarget {
+    pub fn new(host: &HostSettings) -> Self {
+        SshTarget {
+            sshname: host.sshname.clone(),
+            jump_host: host.jump_host.clone(),
+            ssh_args: host.ssh_args.clone(),
+            // %C is a hash of the connection parameters, short enough for socket path limits.
+            control_path: host.multiplex.then(|| paths::control_dir().join("%C")),
+        }
+    }
+
+    /// `ssh -O <operation>` against this host's ControlMaster, e.g. `check` or `exit`.
+    pub fn control(&self, operation: &str) -> ShellCommand {
+        let mut command = self.ssh();
+        command
+            .arg("-O")
+            .arg(operation)
+            .arg("--")
+            .arg(&self.sshname);
+        command
+    }
+
+    /// `ssh` with the jump host and extra arguments applied, before the destination.
+    pub fn ssh(&self) -> ShellCommand {
+        let mut command = ShellCommand::new("ssh");
+        command.args(self.ssh_options());
+        command
+    }
+
+    /// Like [`SshTarget::ssh`], but on a connection of its own. Used for long-lived
+    /// tunnels so that the recorded pid owns the tunnel and a forward started
+    /// first never becomes everyone else's ControlMaster.
+    pub fn ssh_unshared(&self) -> ShellCommand {
+        let mut command = ShellCommand::new("ssh");
+        command
+            .args(["-o", "ControlMaster=no", "-o", "ControlPath=none"])
+            .args(self.connection_options());
+        command
+    }
+
+    /// `ssh` running `argv` on the host with inherited stdio. `tty` forces a
+    /// remote pseudo-terminal; without it none is allocated, keeping pipes byte-clean.
+    pub fn command(&self, argv: &[String], tty: bool) -> ShellCommand {
+        let mut command = self.ssh();
+        command
+            .arg(if tty { "-t" } else { "-T" })
+            .arg("--")
+            .arg(&self.sshname)
+            .arg(command_line(argv));
+        command
+    }
+
+    fn ssh_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(control_path) = &self.control_path {
+            // ssh refuses to create the master socket in a missing directory.
+            if let Some(dir) = control_path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            options.extend([
+                "-o".to_string(),
+                "ControlMaster=auto".to_string(),
+                "-o".to_string(),
+                format!("ControlPath={}", control_path.display()),
+                "-o".to_string(),
+                format!("ControlPersist={}", CONTROL_PERSIST),
+            ]);
+        }
+        options.extend(self.connection_options());
+        options
+    }
+
+    fn connection_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if let Some(jump_host) = &self.jump_host {
+            options.push("-J".to_string());
+            options.push(jump_host.clone());
+        }
+        options.extend(self.ssh_args.iter().cloned());
+        options
+    }
+}
+
+pub trait Transport {
+    /// Local client binary the transport needs on PATH.
+    fn binary(&self) -> &'static str;
+
+    /// Interactive session running `argv` on the host.
+    fn interactive(&self, target: &SshTarget, argv: &[String]) -> ShellCommand;
+}
+
+pub struct Ssh;
+
+impl Transport for Ssh {
+    fn binary(&self) -> &'static str {
+        "ssh"
+    }
+
+    fn interactive(&self, target: &SshTarget, argv: &[String]) -> ShellCommand {
+        target.command(argv, true)
+    }
+}
+
+pub struct Mosh;
+
+impl Mosh {
+    /// Exit status of the `mosh` wrapper when it could not start or reach
+    /// mosh-server, e.g. when mosh-server is not installed on the host.
+    pub const CONNECT_FAILED: i32 = 255;
+}
+
+impl Transport for Mosh {
+    fn binary(&self) -> &'static str {
+        "mosh"
+    }
+
+    fn interactive(&self, target: &SshTarget, argv: &[String]) -> ShellCommand {
+        // mosh hands the arguments after `--` to mosh-server, which execs them
+        // without a shell, so they are passed through unquoted.
+        let mut command = ShellCommand::new("mosh");
+        let ssh_options = target.ssh_options();
+        if !ssh_options.is_empty() {
+            let ssh: Vec<String> = std::iter::once("ssh".to_string())
+                .chain(ssh_options)
+                .collect();
+            command.arg(format!("--ssh={}", command_line(&ssh)));
+        }
+        command.arg(&target.sshname).arg("--").args(argv);
+        command
+    }
+}
+
+/// Eternal Terminal. `et` has no way to pass ssh arguments through, so only
+/// the jump host is honored.
+pub struct EternalTerminal;
+
+impl Transport for EternalTerminal {
+    fn binary(&self) -> &'static str {
+        "et"
+    }
+
+    fn interactive(&self, target: &SshTarget, argv: &[String]) -> ShellCommand {
+        let mut command = ShellCommand::new("et");
+        if let Some(jump_host) = &target.jump_host {
+            command.arg("--jumphost").arg(jump_host);
+        }
+        // et types the command into a login shell; exit with it so the session ends too.
+        command
+            .arg("--command")
+            .arg(format!("{}; exit", command_line(argv)))
+            .arg(&target.sshname);
+        command
+    }
+}
+
+/// `kind` if its client is installed locally, otherwise plain ssh.
+pub fn installed(kind: TransportKind) -> TransportKind {
+    let binary = kind.transport().binary();
+    if !on_path(binary) {
+        eprintln!("{} not found on PATH, falling back to ssh", binary);
+        return TransportKind::Ssh;
+    }
+    kind
+}
+
+pub fn on_path(binary: &str) -> bool {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(binary).is_file()))
+        .unwrap_or(false)
+}